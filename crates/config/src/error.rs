@@ -0,0 +1,56 @@
+use std::fmt;
+
+/// A problem found while loading the config
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    /// Path of the config file
+    pub path: Option<String>,
+    /// Path of the field with the problem, e.g. `hooks["example.com/hello"].out[0].href`
+    pub field: Option<String>,
+    /// Line & column in the config file
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            path: None,
+            field: None,
+            location: None,
+            message: message.into(),
+        }
+    }
+
+    /// Create an error for a specific field
+    pub fn field(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: Some(field.into()),
+            ..Self::new(message)
+        }
+    }
+
+    /// Set which config file the error came from
+    pub fn with_path(self, path: &str) -> Self {
+        Self {
+            path: Some(path.to_owned()),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}", path)?;
+            if let Some((line, column)) = self.location {
+                write!(f, ":{}:{}", line, column)?;
+            }
+            write!(f, ": ")?;
+        }
+        if let Some(field) = &self.field {
+            write!(f, "{}: ", field)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
@@ -0,0 +1,113 @@
+use serde_yaml::Value;
+
+use crate::ConfigError;
+
+/// Replace `${NAME}` (or `${NAME:-default}`) with the environment variable's value,
+/// `$${` can be used for a literal `${`, and a `${` not followed by a variable name
+/// is kept as is so templates such as `${{ content.json.price }}` are left alone
+fn interpolate_str(value: &str) -> Result<String, String> {
+    let mut interpolated = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        interpolated.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            interpolated.push_str("${");
+            rest = after;
+            continue;
+        }
+        let after = match rest.strip_prefix("${") {
+            Some(after) if after.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => {
+                after
+            }
+            _ => {
+                interpolated.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let name_end = after
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .ok_or_else(|| String::from("unterminated \"${\""))?;
+        let name = &after[..name_end];
+        let (default, end) = match &after[name_end..] {
+            next if next.starts_with('}') => (None, name_end),
+            next if next.starts_with(":-") => {
+                let end = after
+                    .find('}')
+                    .ok_or_else(|| String::from("unterminated \"${\""))?;
+                (Some(&after[name_end + 2..end]), end)
+            }
+            // not a reference to a variable, keep it as is
+            _ => {
+                interpolated.push('$');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        match (std::env::var(name), default) {
+            (Ok(value), _) => interpolated.push_str(&value),
+            (Err(_), Some(default)) => interpolated.push_str(default),
+            (Err(_), None) => return Err(format!("environment variable \"{}\" is not set", name)),
+        }
+        rest = &after[end + 1..];
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+/// Interpolate environment variables in every string of a YAML value
+pub fn interpolate_env(value: &mut Value, field: &str, errors: &mut Vec<ConfigError>) {
+    match value {
+        Value::String(string) => match interpolate_str(string) {
+            Ok(interpolated) => *string = interpolated,
+            Err(message) => errors.push(ConfigError::field(field, message)),
+        },
+        Value::Sequence(sequence) => {
+            for (index, value) in sequence.iter_mut().enumerate() {
+                interpolate_env(value, &format!("{}[{}]", field, index), errors);
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping.iter_mut() {
+                let is_identifier =
+                    |key: &str| key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                let field = match key.as_str() {
+                    Some(key) if field.is_empty() => key.to_owned(),
+                    Some(key) if is_identifier(key) => format!("{}.{}", field, key),
+                    Some(key) => format!("{}[\"{}\"]", field, key),
+                    None => field.to_owned(),
+                };
+                interpolate_env(value, &field, errors);
+            }
+        }
+        Value::Tagged(tagged) => interpolate_env(&mut tagged.value, field, errors),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate_str() {
+        std::env::set_var("MIGHTY_HOOKS_TEST_INTERPOLATE", "secret");
+        std::env::remove_var("MIGHTY_HOOKS_TEST_INTERPOLATE_UNSET");
+        assert_eq!(
+            Ok(String::from("a secret $5 ${NOT_ME}")),
+            interpolate_str("a ${MIGHTY_HOOKS_TEST_INTERPOLATE} $5 $${NOT_ME}")
+        );
+        assert_eq!(
+            Ok(String::from("fallback")),
+            interpolate_str("${MIGHTY_HOOKS_TEST_INTERPOLATE_UNSET:-fallback}")
+        );
+        assert!(interpolate_str("${MIGHTY_HOOKS_TEST_INTERPOLATE_UNSET}").is_err());
+        assert!(interpolate_str("${MIGHTY_HOOKS_TEST_INTERPOLATE").is_err());
+        // templates are not variables
+        assert_eq!(
+            Ok(String::from("${{ content.json.price }} ${ x } ${MIGHTY_HOOKS_TEST_INTERPOLATE x}")),
+            interpolate_str("${{ content.json.price }} ${ x } ${MIGHTY_HOOKS_TEST_INTERPOLATE x}")
+        );
+    }
+}
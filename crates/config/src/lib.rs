@@ -1,7 +1,14 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::SystemTime};
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+pub use error::ConfigError;
+
+mod error;
+mod interpolate;
+mod validate;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EnvVarConfig {
     pub log_level: Option<String>,
@@ -14,12 +21,239 @@ impl EnvVarConfig {
     }
 }
 
+/// Deserialize either a single string or a list of strings
+fn string_or_list<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+    match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(value) => Ok(vec![value]),
+        StringOrList::List(values) => Ok(values),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HookIn {
-    /// Expected Content-Type header value
-    pub content_type: String,
+    /// Accepted media types of the Content-Type header,
+    /// given as one or a list, supporting `*` and `*+suffix` wildcards
+    #[serde(deserialize_with = "string_or_list")]
+    pub content_type: Vec<String>,
     /// Secret for HMAC x-hub-signature-256
     pub secret_256: Option<String>,
+    /// Read secret_256 from this file instead
+    pub secret_256_file: Option<String>,
+    /// More secrets to accept, tried in turn after secret_256
+    #[serde(default)]
+    pub secrets: Vec<HookSecret>,
+    /// How requests are signed with the secrets
+    #[serde(default)]
+    pub verify: HookVerify,
+    /// Ignore deliveries that have already been received
+    pub replay_guard: Option<ReplayGuard>,
+    /// Addresses requests may come from as CIDR ranges, any if neither this or the file is set
+    #[serde(default)]
+    pub allowed_sources: Vec<String>,
+    /// Read more allowed sources from this file, reloaded on SIGHUP
+    pub allowed_sources_file: Option<String>,
+    /// Limits on requests to this hook
+    #[serde(default)]
+    pub rate_limit: RateLimits,
+    /// Largest body accepted in bytes, instead of the server's max_body_bytes
+    pub max_body_bytes: Option<usize>,
+}
+
+impl HookIn {
+    /// Whether requests must be signed
+    pub fn has_secrets(&self) -> bool {
+        self.secret_256.is_some() || !self.secrets.is_empty()
+    }
+
+    /// Secrets that have not expired at `now`, with the name to log when one matches
+    pub fn active_secrets(&self, now: SystemTime) -> Vec<(String, &str)> {
+        let now = DateTime::<Utc>::from(now);
+        let secret_256 = self
+            .secret_256
+            .as_deref()
+            .map(|secret| (String::from("secret_256"), secret));
+        let secrets = self
+            .secrets
+            .iter()
+            .enumerate()
+            .filter(|(_, secret)| secret.not_after.is_none_or(|not_after| now <= not_after))
+            .filter_map(|(index, secret)| {
+                let name = match &secret.name {
+                    Some(name) => name.clone(),
+                    None => format!("secrets[{}]", index),
+                };
+                secret.secret.as_deref().map(|value| (name, value))
+            });
+        secret_256.into_iter().chain(secrets).collect()
+    }
+}
+
+/// A secret accepted for incoming requests, so secrets can be rotated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HookSecret {
+    /// Name to log when this secret matches
+    pub name: Option<String>,
+    pub secret: Option<String>,
+    /// Read secret from this file instead
+    pub secret_file: Option<String>,
+    /// Stop accepting this secret after this time (RFC 3339)
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+/// A token bucket limit on requests
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Requests allowed per second, on average
+    pub per_second: f64,
+    /// Requests allowed at once before being limited,
+    /// if not set it is per_second (at least 1)
+    pub burst: Option<u32>,
+}
+
+impl RateLimit {
+    /// Most requests allowed at once
+    pub fn capacity(&self) -> f64 {
+        match self.burst {
+            Some(burst) => burst as f64,
+            None => self.per_second.max(1.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RateLimits {
+    /// Limit on all requests
+    pub total: Option<RateLimit>,
+    /// Limit on requests from each client address
+    pub per_client: Option<RateLimit>,
+}
+
+/// Remembers received deliveries, so duplicates are not sent again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplayGuard {
+    /// Header holding a unique delivery id (e.g. `x-github-delivery`),
+    /// if not set the body's hash is used
+    pub header: Option<String>,
+    /// How long to remember a delivery in seconds
+    #[serde(default = "ReplayGuard::default_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Most deliveries to remember, forgetting the oldest first
+    #[serde(default = "ReplayGuard::default_max_entries")]
+    pub max_entries: usize,
+}
+
+impl ReplayGuard {
+    fn default_ttl_secs() -> u64 {
+        24 * 60 * 60
+    }
+
+    fn default_max_entries() -> usize {
+        10_000
+    }
+}
+
+/// Signature scheme used by the sender
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerifyScheme {
+    /// HMAC SHA256 in `x-hub-signature-256` (`sha256=<hex>`)
+    #[default]
+    Github,
+    /// HMAC SHA1 in `x-hub-signature` (`sha1=<hex>`)
+    GithubSha1,
+    /// HMAC SHA256 in `x-gitea-signature` (hex)
+    Gitea,
+    /// HMAC SHA256 in `x-gogs-signature` (hex)
+    Gogs,
+    /// Secret sent as is in `x-gitlab-token`
+    Gitlab,
+    /// HMAC SHA256 in `x-shopify-hmac-sha256` (base64)
+    Shopify,
+    /// HMAC SHA256 of timestamp and body in `stripe-signature` (`t=<timestamp>,v1=<hex>`)
+    Stripe,
+    /// HMAC SHA256 of timestamp and body in `x-slack-signature` (`v0=<hex>`),
+    /// with the timestamp in `x-slack-request-timestamp`
+    Slack,
+    /// Standard Webhooks HMAC SHA256 of id, timestamp and body in `webhook-signature`
+    /// (`v1,<base64>`), with a base64 secret
+    StandardWebhooks,
+    /// Ed25519 signature of timestamp and body in `x-signature-ed25519` (hex),
+    /// with the timestamp in `x-signature-timestamp` and public keys (hex or PEM) as secrets
+    Ed25519,
+}
+
+impl VerifyScheme {
+    /// Whether the scheme signs a timestamp, so old requests can be rejected
+    pub fn is_timestamped(&self) -> bool {
+        matches!(
+            self,
+            Self::Stripe | Self::Slack | Self::StandardWebhooks | Self::Ed25519
+        )
+    }
+}
+
+/// Encoding of a signature
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureEncoding {
+    Hex,
+    Base64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HookVerify {
+    #[serde(default)]
+    pub scheme: VerifyScheme,
+    /// Header holding the signature, instead of the scheme's
+    pub header: Option<String>,
+    /// Encoding of the signature, instead of the scheme's
+    pub encoding: Option<SignatureEncoding>,
+    /// How far a signed timestamp may be from now in seconds (default 300),
+    /// for timestamped schemes
+    pub tolerance_secs: Option<u64>,
+}
+
+/// How outgoing requests are signed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignScheme {
+    /// HMAC SHA256 in `x-hub-signature-256` (`sha256=<hex>`)
+    #[default]
+    Github,
+    /// Standard Webhooks `webhook-id`, `webhook-timestamp` and `webhook-signature`
+    StandardWebhooks,
+}
+
+/// Formats a body can be decoded from for templates
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BodyFormat {
+    Json,
+    Form,
+    Yaml,
+    Toml,
+    Xml,
+    Msgpack,
+    Cbor,
+    /// Needs a message from `HookReword.protobuf`
+    Protobuf,
+}
+
+/// A Protobuf message to decode bodies as
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProtobufMessage {
+    /// Path of a `FileDescriptorSet`, e.g. from `protoc --descriptor_set_out`
+    pub descriptor: String,
+    /// Full name of the message, e.g. `telemetry.v1.Reading`
+    pub message: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -32,14 +266,74 @@ pub struct HookReword {
     /// Headers to add to new request
     #[serde(default)]
     pub set_headers: HashMap<String, String>,
+    /// Decode the incoming body as this format, instead of by its content type
+    #[serde(default)]
+    pub decode: Option<BodyFormat>,
+    /// Decode the incoming body as this Protobuf message
+    #[serde(default)]
+    pub protobuf: Option<ProtobufMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Maximum number of delivery attempts, including the first
+    #[serde(default = "RetryPolicy::default_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay before the first retry in milliseconds,
+    /// doubled for every following attempt
+    #[serde(default = "RetryPolicy::default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    /// Maximum random delay in milliseconds added to each retry
+    #[serde(default = "RetryPolicy::default_jitter_ms")]
+    pub jitter_ms: u64,
+    /// Upper limit of the delay between attempts in milliseconds
+    #[serde(default = "RetryPolicy::default_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+impl RetryPolicy {
+    fn default_max_attempts() -> u32 {
+        5
+    }
+
+    fn default_base_delay_ms() -> u64 {
+        1000
+    }
+
+    fn default_jitter_ms() -> u64 {
+        500
+    }
+
+    fn default_max_delay_ms() -> u64 {
+        5 * 60 * 1000
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: Self::default_max_attempts(),
+            base_delay_ms: Self::default_base_delay_ms(),
+            jitter_ms: Self::default_jitter_ms(),
+            max_delay_ms: Self::default_max_delay_ms(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HookOut {
     /// Where to forward the webhook to
     pub href: String,
+    /// Only forward when this expression is true,
+    /// evaluated against the incoming headers and JSON body
+    pub when: Option<String>,
     /// Secret for HMAC x-hub-signature-256
     pub secret_256: Option<String>,
+    /// Read secret_256 from this file instead
+    pub secret_256_file: Option<String>,
+    /// How to sign with secret_256
+    #[serde(default)]
+    pub sign: SignScheme,
     /// Headers to keep from the incoming request
     /// - all others will be dropped
     /// - case-insensitive
@@ -48,6 +342,25 @@ pub struct HookOut {
     pub keep_headers: Vec<String>,
     /// Optionally reword (alter hook output) the body
     pub reword: Option<HookReword>,
+    /// How failed deliveries are retried
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Response statuses that count as a successful delivery
+    /// - If empty, any 2xx status is accepted
+    #[serde(default)]
+    pub accept_status: Vec<u16>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HookAck {
+    /// Respond straight away, sending to targets in the background
+    Immediate,
+    /// Wait until every target has received the webhook
+    #[default]
+    All,
+    /// Wait until at least one target has received the webhook
+    Any,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -56,6 +369,9 @@ pub struct Hook {
     pub r#in: HookIn,
     /// Outgoing webhooks
     pub out: Vec<HookOut>,
+    /// When to respond to the sender
+    #[serde(default)]
+    pub ack: HookAck,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -74,28 +390,261 @@ pub struct Config {
     pub port: u16,
     // Setup HTTPS for the server
     pub https: Option<HttpsConfig>,
-    /// Whether the server is behind a reverse proxy
+    /// Replaced by trusted_proxies, kept to report it (`false` is ignored)
+    pub behind_proxy: Option<bool>,
+    /// Reverse proxies (CIDR ranges) trusted to forward the client's address
     #[serde(default)]
-    pub behind_proxy: bool,
+    pub trusted_proxies: Vec<String>,
+    /// Limits on requests to any hook
+    #[serde(default)]
+    pub rate_limit: RateLimits,
+    /// Largest body accepted in bytes, unless set by the hook
+    #[serde(default = "Config::default_max_body_bytes")]
+    pub max_body_bytes: usize,
+    /// Path to serve metrics on (in the Prometheus text format)
+    /// - If not set, metrics are not served
+    pub metrics_path: Option<String>,
+    /// Address (`host:port`) to serve metrics on, instead of with the hooks
+    /// - If not set, metrics are served by the hooks' listener to anyone that can reach it
+    pub metrics_bind: Option<String>,
+    /// Directory to persist state in, such as the retry queue
+    /// - If not set, pending retries are kept in memory only
+    pub data_dir: Option<String>,
     /// FQDN+PATH -> Hook
     pub hooks: HashMap<String, Hook>,
 }
 
+/// Read a secret from a file into the secret field, removing any trailing newline
+fn read_secret_file(
+    field: &str,
+    secret: &mut Option<String>,
+    secret_file: &Option<String>,
+    errors: &mut Vec<ConfigError>,
+) {
+    let path = match secret_file {
+        Some(path) => path,
+        None => return,
+    };
+    if secret.is_some() {
+        errors.push(ConfigError::field(
+            field,
+            "cannot set both secret and secret file",
+        ));
+        return;
+    }
+    match std::fs::read_to_string(path) {
+        Ok(content) => *secret = Some(content.trim_end_matches(['\r', '\n']).to_owned()),
+        Err(err) => errors.push(ConfigError::field(
+            format!("{}_file", field),
+            format!("could not read secret file \"{}\": {}", path, err),
+        )),
+    }
+}
+
 impl Config {
-    /// Load config from file
-    pub fn from_yaml_file(path: &str) -> Result<Self, ()> {
-        match std::fs::File::open(path) {
-            Ok(file) => match serde_yaml::from_reader(file) {
-                Ok(conf) => Ok(conf),
-                Err(_) => {
-                    eprintln!("Error: Could not parse config file: {}", path);
-                    Err(())
+    fn default_max_body_bytes() -> usize {
+        256 * 1024
+    }
+
+    /// Load config from file,
+    /// resolving environment variables and secret files
+    pub fn from_yaml_file(path: &str) -> Result<Self, Vec<ConfigError>> {
+        let config = Self::parse_yaml_file(path).map_err(|err| vec![err])?;
+        config.resolve().map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|err| err.with_path(path))
+                .collect::<Vec<ConfigError>>()
+        })
+    }
+
+    /// Parse config from file, as written
+    fn parse_yaml_file(path: &str) -> Result<Self, ConfigError> {
+        let file = std::fs::File::open(path).map_err(|err| {
+            ConfigError::new(format!("could not open config file: {}", err)).with_path(path)
+        })?;
+        serde_path_to_error::deserialize(serde_yaml::Deserializer::from_reader(file)).map_err(
+            |err| {
+                let field = err.path().to_string();
+                let inner = err.into_inner();
+                let location = inner
+                    .location()
+                    .map(|location| (location.line(), location.column()));
+                // serde_yaml includes the field & location in the message, remove them
+                let mut message = inner.to_string();
+                if let Some(stripped) = message.strip_prefix(&format!("{}: ", field)) {
+                    message = stripped.to_owned();
+                }
+                if let Some((line, column)) = location {
+                    let suffix = format!(" at line {} column {}", line, column);
+                    if let Some(stripped) = message.strip_suffix(&suffix) {
+                        message = stripped.to_owned();
+                    }
+                }
+                ConfigError {
+                    path: Some(path.to_owned()),
+                    field: (field != ".").then_some(field),
+                    location,
+                    message,
                 }
             },
-            Err(_) => {
-                eprintln!("Error: Could not open config file: {}", path);
-                Err(())
+        )
+    }
+
+    /// Interpolate environment variables in all strings and read secret files
+    fn resolve(self) -> Result<Self, Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        let mut value =
+            serde_yaml::to_value(&self).map_err(|err| vec![ConfigError::new(err.to_string())])?;
+        interpolate::interpolate_env(&mut value, "", &mut errors);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let mut config: Self =
+            serde_yaml::from_value(value).map_err(|err| vec![ConfigError::new(err.to_string())])?;
+        for (key, hook) in config.hooks.iter_mut() {
+            let field = format!("hooks[\"{}\"]", key);
+            read_secret_file(
+                &format!("{}.in.secret_256", field),
+                &mut hook.r#in.secret_256,
+                &hook.r#in.secret_256_file,
+                &mut errors,
+            );
+            for (index, secret) in hook.r#in.secrets.iter_mut().enumerate() {
+                read_secret_file(
+                    &format!("{}.in.secrets[{}].secret", field, index),
+                    &mut secret.secret,
+                    &secret.secret_file,
+                    &mut errors,
+                );
+            }
+            for (index, out) in hook.out.iter_mut().enumerate() {
+                read_secret_file(
+                    &format!("{}.out[{}].secret_256", field, index),
+                    &mut out.secret_256,
+                    &out.secret_256_file,
+                    &mut errors,
+                );
             }
         }
+        match errors.is_empty() {
+            true => Ok(config),
+            false => Err(errors),
+        }
+    }
+
+    /// Copy of the config with secrets hidden, for showing or logging it
+    pub fn redacted(&self) -> Self {
+        let redact = |secret: &mut Option<String>| {
+            if secret.is_some() {
+                *secret = Some(String::from("<redacted>"));
+            }
+        };
+        let mut config = self.clone();
+        for hook in config.hooks.values_mut() {
+            redact(&mut hook.r#in.secret_256);
+            for secret in hook.r#in.secrets.iter_mut() {
+                redact(&mut secret.secret);
+            }
+            for out in hook.out.iter_mut() {
+                redact(&mut out.secret_256);
+            }
+        }
+        config
+    }
+
+    /// Load config from file and validate it, returning every problem found
+    pub fn load(path: &str) -> Result<Self, Vec<ConfigError>> {
+        let config = Self::from_yaml_file(path)?;
+        config.validate().map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|err| err.with_path(path))
+                .collect::<Vec<ConfigError>>()
+        })?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_active_secrets() {
+        let hook_in: HookIn = serde_yaml::from_str(
+            r#"
+content_type: application/json
+secret_256: current
+secrets:
+  - name: old
+    secret: old
+    not_after: 2023-03-01T00:00:00Z
+  - secret: next
+"#,
+        )
+        .unwrap();
+        let names = |now| -> Vec<String> {
+            hook_in
+                .active_secrets(now)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()
+        };
+        let before = SystemTime::UNIX_EPOCH + Duration::from_secs(1_677_000_000);
+        let after = SystemTime::UNIX_EPOCH + Duration::from_secs(1_678_000_000);
+        assert_eq!(vec!["secret_256", "old", "secrets[1]"], names(before));
+        assert_eq!(vec!["secret_256", "secrets[1]"], names(after));
+    }
+
+    #[test]
+    fn test_read_secret_file() {
+        let file = std::env::temp_dir().join(format!("mighty-hooks-secret-{}", std::process::id()));
+        std::fs::write(&file, "my_secret\r\n").unwrap();
+        let path = Some(file.to_string_lossy().into_owned());
+        let mut errors = Vec::new();
+        let mut secret = None;
+        read_secret_file("secret_256", &mut secret, &path, &mut errors);
+        assert_eq!(Some(String::from("my_secret")), secret);
+        assert!(errors.is_empty());
+        // cannot set both
+        read_secret_file("secret_256", &mut secret, &path, &mut errors);
+        std::fs::remove_file(file).unwrap();
+        assert_eq!(Some(String::from("secret_256")), errors[0].field);
+        // missing files are reported on the file field
+        let mut secret = None;
+        let missing = Some(String::from("/does/not/exist"));
+        read_secret_file("secret_256", &mut secret, &missing, &mut errors);
+        assert_eq!(None, secret);
+        assert_eq!(Some(String::from("secret_256_file")), errors[1].field);
+    }
+
+    #[test]
+    fn test_redacted() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+host: 0.0.0.0
+port: 8000
+hooks:
+  hooks.example.com/hello:
+    in:
+      content_type: application/json
+      secret_256: in_secret
+      secrets:
+        - secret: old_secret
+    out:
+      - href: http://example.com
+        secret_256: out_secret
+      - href: http://example.com
+"#,
+        )
+        .unwrap();
+        let shown = format!("{:?}", config.redacted());
+        assert!(!shown.contains("_secret"));
+        let hook = &config.redacted().hooks["hooks.example.com/hello"];
+        assert_eq!(Some(String::from("<redacted>")), hook.r#in.secret_256);
+        assert_eq!(None, hook.out[1].secret_256);
     }
 }
@@ -0,0 +1,447 @@
+use std::fs::File;
+
+use http::{HeaderName, HeaderValue};
+use mighty_hooks_core::{media::MediaType, signing, verify};
+
+use crate::{BodyFormat, Config, ConfigError, RateLimits, SignScheme, VerifyScheme};
+
+/// Whether a value contains tera syntax, these can only be checked once rendered
+fn is_template(value: &str) -> bool {
+    value.contains("{{") || value.contains("{%") || value.contains("{#")
+}
+
+/// Replace `{name}` parameter placeholders, so a href can be parsed
+fn strip_params(value: &str) -> String {
+    let mut stripped = String::with_capacity(value.len());
+    let mut in_param = false;
+    for c in value.chars() {
+        match (in_param, c) {
+            (false, '{') => in_param = true,
+            (true, '}') => {
+                in_param = false;
+                stripped.push_str("param");
+            }
+            (false, _) => stripped.push(c),
+            (true, _) => {}
+        }
+    }
+    stripped
+}
+
+fn check_href(field: String, href: &str, errors: &mut Vec<ConfigError>) {
+    if is_template(href) {
+        return;
+    }
+    match url::Url::parse(&strip_params(href)) {
+        Ok(url) => {
+            if url.scheme() != "http" && url.scheme() != "https" {
+                errors.push(ConfigError::field(
+                    field,
+                    format!("unsupported scheme \"{}\"", url.scheme()),
+                ));
+            }
+        }
+        Err(err) => errors.push(ConfigError::field(field, format!("invalid url: {}", err))),
+    }
+}
+
+fn check_header_name(field: String, name: &str, errors: &mut Vec<ConfigError>) {
+    if HeaderName::from_bytes(name.as_bytes()).is_err() {
+        errors.push(ConfigError::field(
+            field,
+            format!("invalid header name \"{}\"", name),
+        ));
+    }
+}
+
+fn check_header_value(field: String, value: &str, errors: &mut Vec<ConfigError>) {
+    if !is_template(value) && HeaderValue::from_str(value).is_err() {
+        errors.push(ConfigError::field(
+            field,
+            format!("invalid header value \"{}\"", value),
+        ));
+    }
+}
+
+/// Check a Standard Webhooks secret is base64, with an optional `whsec_` prefix
+fn check_standard_secret(field: String, secret: &str, errors: &mut Vec<ConfigError>) {
+    if !signing::is_standard_webhook_secret(secret) {
+        errors.push(ConfigError::field(
+            field,
+            "standard webhooks secrets must be base64",
+        ));
+    }
+}
+
+/// Check an Ed25519 public key is 32 bytes of hex, or a PEM `PUBLIC KEY`
+fn check_ed25519_key(field: String, key: &str, errors: &mut Vec<ConfigError>) {
+    if verify::parse_ed25519_public_key(key).is_none() {
+        errors.push(ConfigError::field(
+            field,
+            "ed25519 public keys must be 32 bytes of hex or PEM",
+        ));
+    }
+}
+
+/// Check a secret can be used by the scheme verifying it
+fn check_in_secret(
+    scheme: VerifyScheme,
+    field: String,
+    secret: &str,
+    errors: &mut Vec<ConfigError>,
+) {
+    match scheme {
+        VerifyScheme::StandardWebhooks => check_standard_secret(field, secret, errors),
+        VerifyScheme::Ed25519 => check_ed25519_key(field, secret, errors),
+        _ => {}
+    }
+}
+
+/// Check an address range is valid CIDR notation or a single address
+fn check_ip_range(field: String, value: &str, errors: &mut Vec<ConfigError>) {
+    let value = value.trim();
+    if value.parse::<ipnet::IpNet>().is_err() && value.parse::<std::net::IpAddr>().is_err() {
+        errors.push(ConfigError::field(
+            field,
+            format!("invalid address range \"{}\"", value),
+        ));
+    }
+}
+
+fn check_rate_limits(field: &str, limits: &RateLimits, errors: &mut Vec<ConfigError>) {
+    let limits = [("total", &limits.total), ("per_client", &limits.per_client)];
+    for (name, limit) in limits {
+        let limit = match limit {
+            Some(limit) => limit,
+            None => continue,
+        };
+        if limit.per_second.is_nan() || limit.per_second <= 0.0 {
+            errors.push(ConfigError::field(
+                format!("{}.{}.per_second", field, name),
+                "must be more than 0",
+            ));
+        }
+        if limit.burst == Some(0) {
+            errors.push(ConfigError::field(
+                format!("{}.{}.burst", field, name),
+                "must be at least 1",
+            ));
+        }
+    }
+}
+
+/// Check a media type has a type and subtype, e.g. `application/json`
+fn check_media_type(field: String, value: &str, errors: &mut Vec<ConfigError>) {
+    if MediaType::parse(value).is_none() {
+        errors.push(ConfigError::field(
+            field,
+            format!("invalid media type \"{}\"", value),
+        ));
+    }
+}
+
+fn check_file(field: String, path: &str, errors: &mut Vec<ConfigError>) {
+    if let Err(err) = File::open(path) {
+        errors.push(ConfigError::field(
+            field,
+            format!("cannot read \"{}\": {}", path, err),
+        ));
+    }
+}
+
+impl Config {
+    /// Check for problems that would otherwise only be found while running,
+    /// returning every problem found
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        if let Some(https) = &self.https {
+            check_file(String::from("https.cert"), &https.cert, &mut errors);
+            check_file(String::from("https.key"), &https.key, &mut errors);
+        }
+        match self.behind_proxy {
+            Some(true) => errors.push(ConfigError::field(
+                "behind_proxy",
+                "replaced by trusted_proxies, a list of proxy address ranges",
+            )),
+            // trusting no proxies is still the default, so it only needs removing
+            Some(false) => log::warn!("behind_proxy is deprecated and ignored, remove it"),
+            None => {}
+        }
+        for (index, proxy) in self.trusted_proxies.iter().enumerate() {
+            check_ip_range(format!("trusted_proxies[{}]", index), proxy, &mut errors);
+        }
+        check_rate_limits("rate_limit", &self.rate_limit, &mut errors);
+        if self.max_body_bytes == 0 {
+            errors.push(ConfigError::field("max_body_bytes", "must be at least 1"));
+        }
+        if let Some(bind) = &self.metrics_bind {
+            if bind.parse::<std::net::SocketAddr>().is_err() {
+                errors.push(ConfigError::field(
+                    "metrics_bind",
+                    format!("invalid address \"{}\", expected host:port", bind),
+                ));
+            } else if self.metrics_path.is_none() {
+                errors.push(ConfigError::field("metrics_bind", "metrics_path must be set"));
+            }
+        }
+        let mut keys: Vec<&String> = self.hooks.keys().collect();
+        keys.sort();
+        for key in keys {
+            let hook = &self.hooks[key];
+            let hook_field = format!("hooks[\"{}\"]", key);
+            if hook.out.is_empty() {
+                errors.push(ConfigError::field(
+                    format!("{}.out", hook_field),
+                    "must have at least one target",
+                ));
+            }
+            let verify = &hook.r#in.verify;
+            if let Some(header) = &verify.header {
+                check_header_name(
+                    format!("{}.in.verify.header", hook_field),
+                    header,
+                    &mut errors,
+                );
+            }
+            let fixed_encoding =
+                verify.scheme == VerifyScheme::Gitlab || verify.scheme.is_timestamped();
+            if fixed_encoding && verify.encoding.is_some() {
+                errors.push(ConfigError::field(
+                    format!("{}.in.verify.encoding", hook_field),
+                    "not used by this scheme",
+                ));
+            }
+            if !verify.scheme.is_timestamped() && verify.tolerance_secs.is_some() {
+                errors.push(ConfigError::field(
+                    format!("{}.in.verify.tolerance_secs", hook_field),
+                    "only used by timestamped schemes",
+                ));
+            }
+            for (index, source) in hook.r#in.allowed_sources.iter().enumerate() {
+                check_ip_range(
+                    format!("{}.in.allowed_sources[{}]", hook_field, index),
+                    source,
+                    &mut errors,
+                );
+            }
+            if let Some(path) = &hook.r#in.allowed_sources_file {
+                check_file(
+                    format!("{}.in.allowed_sources_file", hook_field),
+                    path,
+                    &mut errors,
+                );
+            }
+            if hook.r#in.content_type.is_empty() {
+                errors.push(ConfigError::field(
+                    format!("{}.in.content_type", hook_field),
+                    "must have at least one media type",
+                ));
+            }
+            for (index, content_type) in hook.r#in.content_type.iter().enumerate() {
+                check_media_type(
+                    format!("{}.in.content_type[{}]", hook_field, index),
+                    content_type,
+                    &mut errors,
+                );
+            }
+            if hook.r#in.max_body_bytes == Some(0) {
+                errors.push(ConfigError::field(
+                    format!("{}.in.max_body_bytes", hook_field),
+                    "must be at least 1",
+                ));
+            }
+            check_rate_limits(
+                &format!("{}.in.rate_limit", hook_field),
+                &hook.r#in.rate_limit,
+                &mut errors,
+            );
+            if let Some(guard) = &hook.r#in.replay_guard {
+                let guard_field = format!("{}.in.replay_guard", hook_field);
+                if let Some(header) = &guard.header {
+                    check_header_name(format!("{}.header", guard_field), header, &mut errors);
+                }
+                if guard.ttl_secs == 0 {
+                    errors.push(ConfigError::field(
+                        format!("{}.ttl_secs", guard_field),
+                        "must be at least 1",
+                    ));
+                }
+                if guard.max_entries == 0 {
+                    errors.push(ConfigError::field(
+                        format!("{}.max_entries", guard_field),
+                        "must be at least 1",
+                    ));
+                }
+            }
+            if let Some(secret) = &hook.r#in.secret_256 {
+                check_in_secret(
+                    verify.scheme,
+                    format!("{}.in.secret_256", hook_field),
+                    secret,
+                    &mut errors,
+                );
+            }
+            for (index, secret) in hook.r#in.secrets.iter().enumerate() {
+                let field = format!("{}.in.secrets[{}]", hook_field, index);
+                match &secret.secret {
+                    Some(value) => check_in_secret(
+                        verify.scheme,
+                        format!("{}.secret", field),
+                        value,
+                        &mut errors,
+                    ),
+                    None => {
+                        errors.push(ConfigError::field(field, "must set secret or secret_file"))
+                    }
+                }
+            }
+            for (index, out) in hook.out.iter().enumerate() {
+                let out_field = format!("{}.out[{}]", hook_field, index);
+                check_href(format!("{}.href", out_field), &out.href, &mut errors);
+                if let (SignScheme::StandardWebhooks, Some(secret)) = (out.sign, &out.secret_256) {
+                    check_standard_secret(format!("{}.secret_256", out_field), secret, &mut errors);
+                }
+                for (i, name) in out.keep_headers.iter().enumerate() {
+                    check_header_name(
+                        format!("{}.keep_headers[{}]", out_field, i),
+                        name,
+                        &mut errors,
+                    );
+                }
+                if out.retry.max_attempts == 0 {
+                    errors.push(ConfigError::field(
+                        format!("{}.retry.max_attempts", out_field),
+                        "must be at least 1",
+                    ));
+                }
+                if let Some(reword) = &out.reword {
+                    if let Some(content_type) = &reword.content_type {
+                        check_header_value(
+                            format!("{}.reword.content_type", out_field),
+                            content_type,
+                            &mut errors,
+                        );
+                    }
+                    let mut names: Vec<&String> = reword.set_headers.keys().collect();
+                    names.sort();
+                    for name in names {
+                        let field = format!("{}.reword.set_headers[\"{}\"]", out_field, name);
+                        check_header_name(field.clone(), name, &mut errors);
+                        check_header_value(field, &reword.set_headers[name], &mut errors);
+                    }
+                    match (&reword.protobuf, reword.decode) {
+                        (None, Some(BodyFormat::Protobuf)) => errors.push(ConfigError::field(
+                            format!("{}.reword.protobuf", out_field),
+                            "must be set to decode protobuf",
+                        )),
+                        (Some(_), Some(format)) if format != BodyFormat::Protobuf => {
+                            errors.push(ConfigError::field(
+                                format!("{}.reword.decode", out_field),
+                                "must be protobuf (or unset) when protobuf is set",
+                            ))
+                        }
+                        (Some(protobuf), _) => check_file(
+                            format!("{}.reword.protobuf.descriptor", out_field),
+                            &protobuf.descriptor,
+                            &mut errors,
+                        ),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_params() {
+        assert_eq!(
+            "http://param.example.com/repo/param",
+            strip_params("http://{tenant}.example.com/repo/{name}")
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+host: 0.0.0.0
+port: 8000
+https:
+  cert: /does/not/exist.crt
+  key: /does/not/exist.key
+behind_proxy: true
+metrics_bind: localhost
+hooks:
+  hooks.example.com/empty:
+    in:
+      content_type: [application/json, json]
+    out: []
+  hooks.example.com/hello:
+    in:
+      content_type: application/json
+      secrets:
+        - name: old
+      allowed_sources: ["10.0.0.0/8", "10.0.0.1", "10.0.0.0/33"]
+    out:
+      - href: "not a url"
+        keep_headers: ["bad header"]
+        reword:
+          content: "{}"
+          set_headers:
+            x-good: "{{ content.raw }}"
+            x-bad: "new\nline"
+      - href: "http://{name}.example.com/{{ params.name }}"
+        reword:
+          content: "{{ content.data.reading }}"
+          decode: protobuf
+"#,
+        )
+        .unwrap();
+        let fields: Vec<String> = config
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|err| err.field.unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                "https.cert",
+                "https.key",
+                "behind_proxy",
+                "metrics_bind",
+                "hooks[\"hooks.example.com/empty\"].out",
+                "hooks[\"hooks.example.com/empty\"].in.content_type[1]",
+                "hooks[\"hooks.example.com/hello\"].in.allowed_sources[2]",
+                "hooks[\"hooks.example.com/hello\"].in.secrets[0]",
+                "hooks[\"hooks.example.com/hello\"].out[0].href",
+                "hooks[\"hooks.example.com/hello\"].out[0].keep_headers[0]",
+                "hooks[\"hooks.example.com/hello\"].out[0].reword.set_headers[\"x-bad\"]",
+                "hooks[\"hooks.example.com/hello\"].out[1].reword.protobuf",
+            ],
+            fields
+        );
+    }
+
+    #[test]
+    fn test_validate_behind_proxy_false() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+host: 0.0.0.0
+port: 8000
+behind_proxy: false
+hooks: {}
+"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+    }
+}
@@ -1,5 +1,8 @@
+pub mod media;
+pub mod metrics;
 pub mod signing;
 pub mod tls;
+pub mod verify;
 
 use bytes::Bytes;
 
@@ -0,0 +1,208 @@
+/// A parsed media type, e.g. from a `Content-Type` header
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaType {
+    /// Top level type, lowercase
+    pub r#type: String,
+    /// Subtype, lowercase
+    pub subtype: String,
+    /// Parameters, with lowercase names
+    pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    /// Parse `type/subtype; name=value`, returning None if it is not a media type
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.split(';');
+        let (r#type, subtype) = parts.next()?.trim().split_once('/')?;
+        if r#type.is_empty() || subtype.is_empty() {
+            return None;
+        }
+        let params = parts
+            .filter(|part| !part.trim().is_empty())
+            .map(|part| {
+                let (name, value) = part.split_once('=')?;
+                Some((
+                    name.trim().to_ascii_lowercase(),
+                    value.trim().trim_matches('"').to_owned(),
+                ))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            r#type: r#type.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            params,
+        })
+    }
+
+    /// The type without parameters, e.g. `application/json`
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.r#type, self.subtype)
+    }
+
+    /// The structured syntax suffix, e.g. `json` for `application/vnd.github+json`
+    pub fn suffix(&self) -> Option<&str> {
+        self.subtype.rsplit_once('+').map(|(_, suffix)| suffix)
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether a media type is matched by this one as a pattern,
+    /// `*` matches any type or subtype and `*+json` matches subtypes with that suffix;
+    /// parameters are only compared when the pattern has them
+    pub fn matches(&self, media_type: &MediaType) -> bool {
+        let type_matches = self.r#type == "*" || self.r#type == media_type.r#type;
+        let subtype_matches = match self.subtype.strip_prefix("*+") {
+            Some(suffix) => media_type.suffix() == Some(suffix) || media_type.subtype == suffix,
+            None => self.subtype == "*" || self.subtype == media_type.subtype,
+        };
+        type_matches
+            && subtype_matches
+            && self.params.iter().all(|(name, value)| {
+                media_type
+                    .param(name)
+                    .is_some_and(|other| other.eq_ignore_ascii_case(value))
+            })
+    }
+
+    /// This media type as matched by a pattern, its essence with only the parameters
+    /// the pattern has, e.g. `text/plain; charset=utf-8; x=y` matched by
+    /// `text/*; charset=utf-8` is `text/plain; charset=utf-8`
+    pub fn matched_as(&self, pattern: &MediaType) -> String {
+        let mut matched = self.essence();
+        for (name, _) in &pattern.params {
+            if let Some(value) = self.param(name) {
+                let is_token = !value.is_empty()
+                    && value
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+                match is_token {
+                    true => matched.push_str(&format!("; {}={}", name, value)),
+                    false => matched.push_str(&format!("; {}=\"{}\"", name, value)),
+                }
+            }
+        }
+        matched
+    }
+}
+
+/// Whether a content type holds JSON, e.g. `application/json` or `application/ld+json`
+pub fn is_json(content_type: &str) -> bool {
+    MediaType::parse(content_type).is_some_and(|media_type| {
+        media_type.essence() == "application/json" || media_type.suffix() == Some("json")
+    })
+}
+
+/// Whether a content type holds YAML, e.g. `application/yaml` or `text/x-yaml`
+pub fn is_yaml(content_type: &str) -> bool {
+    MediaType::parse(content_type).is_some_and(|media_type| {
+        matches!(media_type.subtype.as_str(), "yaml" | "x-yaml" | "vnd.yaml")
+            || media_type.suffix() == Some("yaml")
+    })
+}
+
+/// Whether a content type holds TOML, `application/toml`
+pub fn is_toml(content_type: &str) -> bool {
+    MediaType::parse(content_type).is_some_and(|media_type| media_type.subtype == "toml")
+}
+
+/// Whether a content type holds XML, e.g. `application/xml` or `application/atom+xml`
+pub fn is_xml(content_type: &str) -> bool {
+    MediaType::parse(content_type)
+        .is_some_and(|media_type| media_type.subtype == "xml" || media_type.suffix() == Some("xml"))
+}
+
+/// Whether a content type holds MessagePack, e.g. `application/msgpack`
+pub fn is_msgpack(content_type: &str) -> bool {
+    MediaType::parse(content_type).is_some_and(|media_type| {
+        matches!(
+            media_type.subtype.as_str(),
+            "msgpack" | "x-msgpack" | "vnd.msgpack"
+        )
+    })
+}
+
+/// Whether a content type holds CBOR, e.g. `application/cbor` or `application/senml+cbor`
+pub fn is_cbor(content_type: &str) -> bool {
+    MediaType::parse(content_type).is_some_and(|media_type| {
+        media_type.subtype == "cbor" || media_type.suffix() == Some("cbor")
+    })
+}
+
+/// Whether a content type holds a form, `application/x-www-form-urlencoded`
+pub fn is_form(content_type: &str) -> bool {
+    MediaType::parse(content_type)
+        .is_some_and(|media_type| media_type.essence() == "application/x-www-form-urlencoded")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, value: &str) -> bool {
+        MediaType::parse(pattern)
+            .unwrap()
+            .matches(&MediaType::parse(value).unwrap())
+    }
+
+    #[test]
+    fn test_parse() {
+        let media_type = MediaType::parse("Application/JSON; charset=\"UTF-8\"").unwrap();
+        assert_eq!("application/json", media_type.essence());
+        assert_eq!(Some("UTF-8"), media_type.param("charset"));
+        assert_eq!(None, MediaType::parse("json"));
+        assert_eq!(None, MediaType::parse("application/json; charset"));
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches(
+            "application/json",
+            "application/json; charset=utf-8"
+        ));
+        assert!(!matches("application/json", "application/xml"));
+        assert!(matches("application/*", "application/xml"));
+        assert!(matches("*/*", "text/plain"));
+        assert!(matches("application/*+json", "application/vnd.github+json"));
+        assert!(matches("application/*+json", "application/json"));
+        assert!(!matches("application/*+json", "application/xml"));
+        assert!(matches(
+            "text/plain; charset=utf-8",
+            "text/plain; charset=UTF-8"
+        ));
+        assert!(!matches("text/plain; charset=utf-8", "text/plain"));
+    }
+
+    #[test]
+    fn test_matched_as() {
+        let media_type = MediaType::parse("Text/Plain; charset=UTF-8; x=\"a b\"").unwrap();
+        let matched_as = |pattern| media_type.matched_as(&MediaType::parse(pattern).unwrap());
+        assert_eq!("text/plain", matched_as("text/*"));
+        assert_eq!("text/plain; charset=UTF-8", matched_as("text/plain; charset=utf-8"));
+        assert_eq!("text/plain; x=\"a b\"", matched_as("*/*; x=\"a b\""));
+    }
+
+    #[test]
+    fn test_is_json() {
+        assert!(is_json("application/json; charset=utf-8"));
+        assert!(is_json("application/ld+json"));
+        assert!(!is_json("text/plain"));
+    }
+
+    #[test]
+    fn test_formats() {
+        assert!(is_yaml("application/x-yaml"));
+        assert!(is_yaml("text/yaml; charset=utf-8"));
+        assert!(is_toml("application/toml"));
+        assert!(is_xml("text/xml"));
+        assert!(is_xml("application/atom+xml"));
+        assert!(!is_xml("application/json"));
+        assert!(is_form("application/x-www-form-urlencoded"));
+        assert!(is_msgpack("application/x-msgpack"));
+        assert!(is_cbor("application/senml+cbor"));
+    }
+}
@@ -0,0 +1,79 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+/// Escape a label value for the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Counters collected while running,
+/// which can be rendered in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<(&'static str, String), u64>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Increment a counter, identified by its name and labels
+    pub fn inc(&self, name: &'static str, labels: &[(&str, &str)]) {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+            .collect::<Vec<String>>()
+            .join(",");
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry((name, labels))
+            .or_default() += 1;
+    }
+
+    /// Render all counters in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let mut last_name = None;
+        for ((name, labels), value) in self.counters.lock().unwrap().iter() {
+            if last_name != Some(name) {
+                writeln!(output, "# TYPE {} counter", name).unwrap();
+                last_name = Some(name);
+            }
+            match labels.is_empty() {
+                true => writeln!(output, "{} {}", name, value).unwrap(),
+                false => writeln!(output, "{}{{{}}} {}", name, labels, value).unwrap(),
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        metrics.inc("b_total", &[]);
+        metrics.inc("a_total", &[("kind", "5xx")]);
+        metrics.inc("a_total", &[("kind", "5xx")]);
+        metrics.inc("a_total", &[("kind", "timeout")]);
+        let expected = "# TYPE a_total counter\n\
+            a_total{kind=\"5xx\"} 2\n\
+            a_total{kind=\"timeout\"} 1\n\
+            # TYPE b_total counter\n\
+            b_total 1\n";
+        assert_eq!(expected, metrics.render());
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!("a\\\\b\\\"c\\nd", escape_label("a\\b\"c\nd"));
+    }
+}
@@ -1,5 +1,9 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
 use bytes::Bytes;
-use ring::hmac;
+use ring::{digest, hmac};
+
+/// Prefix of Standard Webhooks secrets
+const STANDARD_SECRET_PREFIX: &str = "whsec_";
 
 /// Sign a string with a secret using HMAC SHA256 and return the signature as a hex string
 pub fn sign_hmac_sha256(secret: &str, data: &Bytes) -> String {
@@ -10,8 +14,13 @@ pub fn sign_hmac_sha256(secret: &str, data: &Bytes) -> String {
     hex::encode(signature)
 }
 
+/// Hash data using SHA256 and return the hash as a hex string
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(digest::digest(&digest::SHA256, data))
+}
+
 /// Verify a signed string with a secret using HMAC SHA256 and return true if it is valid
-pub fn verify_hmac_sha256(secret: &str, data: &Bytes, signature_hex: &str) -> bool {
+pub fn verify_hmac_sha256(secret: &str, data: &[u8], signature_hex: &str) -> bool {
     match hex::decode(signature_hex) {
         Ok(signature) => {
             let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
@@ -21,6 +30,60 @@ pub fn verify_hmac_sha256(secret: &str, data: &Bytes, signature_hex: &str) -> bo
     }
 }
 
+/// Decode a Standard Webhooks secret, base64 with an optional `whsec_` prefix
+fn standard_webhook_key(secret: &str) -> Option<hmac::Key> {
+    let secret = secret
+        .strip_prefix(STANDARD_SECRET_PREFIX)
+        .unwrap_or(secret);
+    let secret = STANDARD.decode(secret).ok()?;
+    Some(hmac::Key::new(hmac::HMAC_SHA256, &secret))
+}
+
+/// Content signed by Standard Webhooks, `<id>.<timestamp>.<body>`
+fn standard_webhook_content(id: &str, timestamp: &str, data: &Bytes) -> Vec<u8> {
+    [id.as_bytes(), b".", timestamp.as_bytes(), b".", data].concat()
+}
+
+/// Whether a secret can be used for Standard Webhooks
+pub fn is_standard_webhook_secret(secret: &str) -> bool {
+    standard_webhook_key(secret).is_some()
+}
+
+/// Sign a message using Standard Webhooks and return the `webhook-signature` value,
+/// or None if the secret is not valid base64
+pub fn sign_standard_webhook(
+    secret: &str,
+    id: &str,
+    timestamp: u64,
+    data: &Bytes,
+) -> Option<String> {
+    let key = standard_webhook_key(secret)?;
+    let content = standard_webhook_content(id, &timestamp.to_string(), data);
+    let signature = hmac::sign(&key, &content);
+    Some(format!("v1,{}", STANDARD.encode(signature.as_ref())))
+}
+
+/// Verify a Standard Webhooks message,
+/// returning true if any of the space separated `v1,<base64>` signatures is valid
+pub fn verify_standard_webhook(
+    secret: &str,
+    id: &str,
+    timestamp: &str,
+    data: &Bytes,
+    signatures: &str,
+) -> bool {
+    let key = match standard_webhook_key(secret) {
+        Some(key) => key,
+        None => return false,
+    };
+    let content = standard_webhook_content(id, timestamp, data);
+    signatures
+        .split_whitespace()
+        .filter_map(|signature| signature.strip_prefix("v1,"))
+        .filter_map(|signature| STANDARD.decode(signature).ok())
+        .any(|signature| hmac::verify(&key, &content, &signature).is_ok())
+}
+
 #[cfg(test)]
 mod tests {
     use bytes::Bytes;
@@ -36,6 +99,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_verify_hmac_sha256() {
         let signature =
             String::from("ffad4a016b7e758451f02a86a9c9b504be8e70c0df26afbab7dccc8ddeb90a71");
@@ -47,4 +111,30 @@ mod tests {
             verify_hmac_sha256("my-secret", &Bytes::from("not-my-data"), &signature);
         assert_eq!(false, result_false_2);
     }
+
+    #[test]
+    fn test_standard_webhook() {
+        // example from the Standard Webhooks specification
+        let secret = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw";
+        let body = Bytes::from(r#"{"test": 2432232314}"#);
+        let signature =
+            sign_standard_webhook(secret, "msg_p5jXN8AQM9LWM0D4loKWxJek", 1614265330, &body)
+                .unwrap();
+        assert_eq!("v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE=", signature);
+        let signatures = format!("v1,bm90IGl0 {}", signature);
+        assert!(verify_standard_webhook(
+            secret,
+            "msg_p5jXN8AQM9LWM0D4loKWxJek",
+            "1614265330",
+            &body,
+            &signatures
+        ));
+        assert!(!verify_standard_webhook(
+            secret,
+            "msg_other",
+            "1614265330",
+            &body,
+            &signatures
+        ));
+    }
 }
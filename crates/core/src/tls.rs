@@ -1,11 +1,30 @@
-use std::{fs::File, io::BufReader};
+use std::{fmt, fs::File, io::BufReader};
 
 use rustls::{Certificate, PrivateKey, ServerConfig};
 use rustls_pemfile::{certs, pkcs8_private_keys};
 
+#[derive(Debug)]
+pub enum TlsError {
+    /// A file could not be read
+    Read(String, std::io::Error),
+    /// No PKCS 8 private keys found in key file
+    NoPrivateKey,
+    /// The key/cert data is not valid
+    Invalid(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read(path, err) => write!(f, "could not read \"{}\": {}", path, err),
+            Self::NoPrivateKey => write!(f, "no PKCS 8 private keys found in key file"),
+            Self::Invalid(err) => write!(f, "could not load key/cert data: {}", err),
+        }
+    }
+}
+
 /// Load key/cert files and convert them to rustls objects for use with the web server
-/// TODO add error handling
-pub fn load_rustls_config(cert_path: &str, key_path: &str) -> rustls::ServerConfig {
+pub fn load_rustls_config(cert_path: &str, key_path: &str) -> Result<ServerConfig, TlsError> {
     // code modified from: github.com/actix/examples
     // init server config builder with safe defaults
     let config = ServerConfig::builder()
@@ -13,27 +32,32 @@ pub fn load_rustls_config(cert_path: &str, key_path: &str) -> rustls::ServerConf
         .with_no_client_auth();
 
     // load TLS key/cert files
-    let cert_file = &mut BufReader::new(File::open(cert_path).expect("could not load cert file"));
-    let key_file = &mut BufReader::new(File::open(key_path).expect("could not load key file"));
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| TlsError::Read(path.to_owned(), err))
+    };
+    let cert_file = &mut open(cert_path)?;
+    let key_file = &mut open(key_path)?;
 
     // convert files to key/cert objects
     let cert_chain = certs(cert_file)
-        .unwrap()
+        .map_err(|err| TlsError::Read(cert_path.to_owned(), err))?
         .into_iter()
         .map(Certificate)
         .collect();
     let mut keys: Vec<PrivateKey> = pkcs8_private_keys(key_file)
-        .unwrap()
+        .map_err(|err| TlsError::Read(key_path.to_owned(), err))?
         .into_iter()
         .map(PrivateKey)
         .collect();
 
     // check that keys have been loaded
     if keys.is_empty() {
-        panic!("No PKCS 8 private keys found in key file");
+        return Err(TlsError::NoPrivateKey);
     }
 
     config
         .with_single_cert(cert_chain, keys.remove(0))
-        .expect("could not load key/cert data")
+        .map_err(TlsError::Invalid)
 }
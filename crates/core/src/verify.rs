@@ -0,0 +1,584 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use ring::{constant_time, hmac, signature};
+
+use crate::signing::{verify_hmac_sha256, verify_standard_webhook};
+
+/// Why an incoming request could not be verified
+#[derive(Debug, PartialEq)]
+pub enum VerifyError {
+    /// The header holding the signature is missing
+    Missing(String),
+    /// The signature does not match
+    Invalid,
+    /// The signature matches but the signed timestamp is outside the tolerance,
+    /// so the request may be replayed
+    Expired,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(header) => write!(f, "missing \"{}\" header", header),
+            Self::Invalid => write!(f, "invalid signature"),
+            Self::Expired => write!(f, "signed timestamp outside tolerance"),
+        }
+    }
+}
+
+/// How a signature is written in its header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Hex,
+    Base64,
+}
+
+impl Encoding {
+    fn decode(&self, value: &str) -> Option<Vec<u8>> {
+        match self {
+            Self::Hex => hex::decode(value).ok(),
+            Self::Base64 => STANDARD.decode(value).ok(),
+        }
+    }
+}
+
+/// Checks an incoming request was sent by someone who knows the secret
+pub trait Verifier: Send + Sync {
+    /// Verify a request, `headers` must have lowercase names
+    fn verify(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &Bytes,
+        secret: &str,
+    ) -> Result<(), VerifyError>;
+}
+
+/// Get a header that must be set, `name` must be lowercase
+fn required_header<'a>(
+    headers: &'a HashMap<String, String>,
+    name: &str,
+) -> Result<&'a str, VerifyError> {
+    match headers.get(name) {
+        Some(value) if !value.is_empty() => Ok(value),
+        _ => Err(VerifyError::Missing(name.to_owned())),
+    }
+}
+
+/// Check a signed unix timestamp is within `tolerance` of `now`
+fn check_timestamp(
+    timestamp: &str,
+    tolerance: Duration,
+    now: SystemTime,
+) -> Result<(), VerifyError> {
+    let timestamp: u64 = timestamp.trim().parse().map_err(|_| VerifyError::Invalid)?;
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    match now.abs_diff(timestamp) <= tolerance.as_secs() {
+        true => Ok(()),
+        false => Err(VerifyError::Expired),
+    }
+}
+
+/// HMAC of the body, e.g. GitHub's `X-Hub-Signature-256: sha256=<hex>`
+#[derive(Debug, Clone)]
+pub struct HmacVerifier {
+    header: String,
+    algorithm: hmac::Algorithm,
+    encoding: Encoding,
+    prefix: String,
+}
+
+impl HmacVerifier {
+    pub fn new(header: &str, algorithm: hmac::Algorithm, encoding: Encoding, prefix: &str) -> Self {
+        Self {
+            header: header.to_ascii_lowercase(),
+            algorithm,
+            encoding,
+            prefix: prefix.to_owned(),
+        }
+    }
+
+    /// GitHub `X-Hub-Signature-256`
+    pub fn github() -> Self {
+        Self::new(
+            "X-Hub-Signature-256",
+            hmac::HMAC_SHA256,
+            Encoding::Hex,
+            "sha256=",
+        )
+    }
+
+    /// GitHub's legacy `X-Hub-Signature`
+    pub fn github_sha1() -> Self {
+        Self::new(
+            "X-Hub-Signature",
+            hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            Encoding::Hex,
+            "sha1=",
+        )
+    }
+
+    /// Gitea `X-Gitea-Signature`
+    pub fn gitea() -> Self {
+        Self::new("X-Gitea-Signature", hmac::HMAC_SHA256, Encoding::Hex, "")
+    }
+
+    /// Gogs `X-Gogs-Signature`
+    pub fn gogs() -> Self {
+        Self::new("X-Gogs-Signature", hmac::HMAC_SHA256, Encoding::Hex, "")
+    }
+
+    /// Shopify `X-Shopify-Hmac-Sha256`
+    pub fn shopify() -> Self {
+        Self::new(
+            "X-Shopify-Hmac-Sha256",
+            hmac::HMAC_SHA256,
+            Encoding::Base64,
+            "",
+        )
+    }
+
+    pub fn with_header(mut self, header: &str) -> Self {
+        self.header = header.to_ascii_lowercase();
+        self
+    }
+
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+}
+
+impl Verifier for HmacVerifier {
+    fn verify(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &Bytes,
+        secret: &str,
+    ) -> Result<(), VerifyError> {
+        let value = required_header(headers, &self.header)?;
+        let signature = value
+            .strip_prefix(self.prefix.as_str())
+            .and_then(|signature| self.encoding.decode(signature))
+            .ok_or(VerifyError::Invalid)?;
+        let key = hmac::Key::new(self.algorithm, secret.as_bytes());
+        hmac::verify(&key, body, &signature).map_err(|_| VerifyError::Invalid)
+    }
+}
+
+/// A shared token sent as is, e.g. GitLab's `X-Gitlab-Token`
+#[derive(Debug, Clone)]
+pub struct TokenVerifier {
+    header: String,
+}
+
+impl TokenVerifier {
+    pub fn new(header: &str) -> Self {
+        Self {
+            header: header.to_ascii_lowercase(),
+        }
+    }
+
+    /// GitLab `X-Gitlab-Token`
+    pub fn gitlab() -> Self {
+        Self::new("X-Gitlab-Token")
+    }
+}
+
+impl Verifier for TokenVerifier {
+    fn verify(
+        &self,
+        headers: &HashMap<String, String>,
+        _body: &Bytes,
+        secret: &str,
+    ) -> Result<(), VerifyError> {
+        let token = required_header(headers, &self.header)?;
+        constant_time::verify_slices_are_equal(token.as_bytes(), secret.as_bytes())
+            .map_err(|_| VerifyError::Invalid)
+    }
+}
+
+/// Default time a timestamped signature is accepted for
+pub const DEFAULT_TOLERANCE: Duration = Duration::from_secs(5 * 60);
+
+/// Stripe `Stripe-Signature: t=<timestamp>,v1=<hex>`,
+/// signing `<timestamp>.<body>`
+#[derive(Debug, Clone)]
+pub struct StripeVerifier {
+    header: String,
+    tolerance: Duration,
+}
+
+impl StripeVerifier {
+    pub fn new(tolerance: Duration) -> Self {
+        Self {
+            header: String::from("stripe-signature"),
+            tolerance,
+        }
+    }
+
+    pub fn with_header(mut self, header: &str) -> Self {
+        self.header = header.to_ascii_lowercase();
+        self
+    }
+
+    /// Verify a request as if it was received at `now`
+    pub fn verify_at(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &Bytes,
+        secret: &str,
+        now: SystemTime,
+    ) -> Result<(), VerifyError> {
+        let value = required_header(headers, &self.header)?;
+        let mut timestamp = None;
+        let mut signatures = Vec::new();
+        for (key, value) in value.split(',').filter_map(|item| item.split_once('=')) {
+            match key.trim() {
+                "t" => timestamp = Some(value),
+                "v1" => signatures.push(value),
+                _ => {}
+            }
+        }
+        let timestamp = timestamp.ok_or(VerifyError::Invalid)?;
+        let signed = [timestamp.as_bytes(), b".", body].concat();
+        if !signatures
+            .iter()
+            .any(|signature| verify_hmac_sha256(secret, &signed, signature))
+        {
+            return Err(VerifyError::Invalid);
+        }
+        check_timestamp(timestamp, self.tolerance, now)
+    }
+}
+
+impl Verifier for StripeVerifier {
+    fn verify(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &Bytes,
+        secret: &str,
+    ) -> Result<(), VerifyError> {
+        self.verify_at(headers, body, secret, SystemTime::now())
+    }
+}
+
+/// Slack `X-Slack-Signature: v0=<hex>` with `X-Slack-Request-Timestamp`,
+/// signing `v0:<timestamp>:<body>`
+#[derive(Debug, Clone)]
+pub struct SlackVerifier {
+    header: String,
+    timestamp_header: String,
+    tolerance: Duration,
+}
+
+impl SlackVerifier {
+    pub fn new(tolerance: Duration) -> Self {
+        Self {
+            header: String::from("x-slack-signature"),
+            timestamp_header: String::from("x-slack-request-timestamp"),
+            tolerance,
+        }
+    }
+
+    pub fn with_header(mut self, header: &str) -> Self {
+        self.header = header.to_ascii_lowercase();
+        self
+    }
+
+    /// Verify a request as if it was received at `now`
+    pub fn verify_at(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &Bytes,
+        secret: &str,
+        now: SystemTime,
+    ) -> Result<(), VerifyError> {
+        let value = required_header(headers, &self.header)?;
+        let timestamp = required_header(headers, &self.timestamp_header)?;
+        let signature = value.strip_prefix("v0=").ok_or(VerifyError::Invalid)?;
+        let signed = [b"v0:", timestamp.as_bytes(), b":", body].concat();
+        if !verify_hmac_sha256(secret, &signed, signature) {
+            return Err(VerifyError::Invalid);
+        }
+        check_timestamp(timestamp, self.tolerance, now)
+    }
+}
+
+impl Verifier for SlackVerifier {
+    fn verify(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &Bytes,
+        secret: &str,
+    ) -> Result<(), VerifyError> {
+        self.verify_at(headers, body, secret, SystemTime::now())
+    }
+}
+
+/// Standard Webhooks `webhook-signature: v1,<base64>` with `webhook-id` and `webhook-timestamp`,
+/// signing `<id>.<timestamp>.<body>`
+#[derive(Debug, Clone)]
+pub struct StandardWebhookVerifier {
+    header: String,
+    tolerance: Duration,
+}
+
+impl StandardWebhookVerifier {
+    pub fn new(tolerance: Duration) -> Self {
+        Self {
+            header: String::from("webhook-signature"),
+            tolerance,
+        }
+    }
+
+    pub fn with_header(mut self, header: &str) -> Self {
+        self.header = header.to_ascii_lowercase();
+        self
+    }
+
+    /// Verify a request as if it was received at `now`
+    pub fn verify_at(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &Bytes,
+        secret: &str,
+        now: SystemTime,
+    ) -> Result<(), VerifyError> {
+        let signatures = required_header(headers, &self.header)?;
+        let id = required_header(headers, "webhook-id")?;
+        let timestamp = required_header(headers, "webhook-timestamp")?;
+        if !verify_standard_webhook(secret, id, timestamp, body, signatures) {
+            return Err(VerifyError::Invalid);
+        }
+        check_timestamp(timestamp, self.tolerance, now)
+    }
+}
+
+impl Verifier for StandardWebhookVerifier {
+    fn verify(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &Bytes,
+        secret: &str,
+    ) -> Result<(), VerifyError> {
+        self.verify_at(headers, body, secret, SystemTime::now())
+    }
+}
+
+/// DER prefix of an Ed25519 SubjectPublicKeyInfo, followed by the 32 byte key
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Parse an Ed25519 public key given as hex or a PEM `PUBLIC KEY`
+pub fn parse_ed25519_public_key(key: &str) -> Option<Vec<u8>> {
+    let key = key.trim();
+    let raw = match key.starts_with("-----BEGIN PUBLIC KEY-----") {
+        true => {
+            let encoded: String = key
+                .lines()
+                .filter(|line| !line.starts_with("-----"))
+                .collect::<Vec<_>>()
+                .join("");
+            let der = STANDARD.decode(encoded.trim()).ok()?;
+            der.strip_prefix(&ED25519_SPKI_PREFIX[..])?.to_vec()
+        }
+        false => hex::decode(key).ok()?,
+    };
+    match raw.len() {
+        32 => Some(raw),
+        _ => None,
+    }
+}
+
+/// Ed25519 signature of timestamp and body, e.g. Discord's `X-Signature-Ed25519`
+/// with `X-Signature-Timestamp`, where the secret is the sender's public key
+#[derive(Debug, Clone)]
+pub struct Ed25519Verifier {
+    header: String,
+    timestamp_header: String,
+    tolerance: Duration,
+}
+
+impl Ed25519Verifier {
+    pub fn new(tolerance: Duration) -> Self {
+        Self {
+            header: String::from("x-signature-ed25519"),
+            timestamp_header: String::from("x-signature-timestamp"),
+            tolerance,
+        }
+    }
+
+    pub fn with_header(mut self, header: &str) -> Self {
+        self.header = header.to_ascii_lowercase();
+        self
+    }
+
+    /// Verify a request as if it was received at `now`
+    pub fn verify_at(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &Bytes,
+        public_key: &str,
+        now: SystemTime,
+    ) -> Result<(), VerifyError> {
+        let value = required_header(headers, &self.header)?;
+        let timestamp = required_header(headers, &self.timestamp_header)?;
+        let public_key = parse_ed25519_public_key(public_key).ok_or(VerifyError::Invalid)?;
+        let signature = hex::decode(value).map_err(|_| VerifyError::Invalid)?;
+        let signed = [timestamp.as_bytes(), body].concat();
+        signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+            .verify(&signed, &signature)
+            .map_err(|_| VerifyError::Invalid)?;
+        check_timestamp(timestamp, self.tolerance, now)
+    }
+}
+
+impl Verifier for Ed25519Verifier {
+    fn verify(
+        &self,
+        headers: &HashMap<String, String>,
+        body: &Bytes,
+        public_key: &str,
+    ) -> Result<(), VerifyError> {
+        self.verify_at(headers, body, public_key, SystemTime::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: &str, value: &str) -> HashMap<String, String> {
+        HashMap::from([(name.to_owned(), value.to_owned())])
+    }
+
+    #[test]
+    fn test_github() {
+        let body = Bytes::from("my-data");
+        let headers = headers(
+            "x-hub-signature-256",
+            "sha256=ffad4a016b7e758451f02a86a9c9b504be8e70c0df26afbab7dccc8ddeb90a71",
+        );
+        let verifier = HmacVerifier::github();
+        assert_eq!(Ok(()), verifier.verify(&headers, &body, "my-secret"));
+        assert_eq!(
+            Err(VerifyError::Invalid),
+            verifier.verify(&headers, &body, "not-my-secret")
+        );
+        assert_eq!(
+            Err(VerifyError::Missing(String::from("x-gitea-signature"))),
+            HmacVerifier::gitea().verify(&headers, &body, "my-secret")
+        );
+    }
+
+    #[test]
+    fn test_base64() {
+        let body = Bytes::from("my-data");
+        let headers = headers(
+            "x-shopify-hmac-sha256",
+            "/61KAWt+dYRR8CqGqcm1BL6OcMDfJq+6t9zMjd65CnE=",
+        );
+        let verifier = HmacVerifier::shopify();
+        assert_eq!(Ok(()), verifier.verify(&headers, &body, "my-secret"));
+    }
+
+    #[test]
+    fn test_token() {
+        let headers = headers("x-gitlab-token", "my-token");
+        let verifier = TokenVerifier::gitlab();
+        let body = Bytes::new();
+        assert_eq!(Ok(()), verifier.verify(&headers, &body, "my-token"));
+        assert_eq!(
+            Err(VerifyError::Invalid),
+            verifier.verify(&headers, &body, "my-tokens")
+        );
+    }
+
+    fn sign_hex(secret: &str, data: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        hex::encode(hmac::sign(&key, data.as_bytes()))
+    }
+
+    #[test]
+    fn test_stripe() {
+        let body = Bytes::from(r#"{"id": "evt_1"}"#);
+        let signature = sign_hex("whsec", r#"1700000000.{"id": "evt_1"}"#);
+        let headers = headers(
+            "stripe-signature",
+            &format!("t=1700000000,v1=0000,v1={},v0=0000", signature),
+        );
+        let verifier = StripeVerifier::new(DEFAULT_TOLERANCE);
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(
+            Ok(()),
+            verifier.verify_at(&headers, &body, "whsec", at(1_700_000_100))
+        );
+        assert_eq!(
+            Err(VerifyError::Expired),
+            verifier.verify_at(&headers, &body, "whsec", at(1_700_000_301))
+        );
+        assert_eq!(
+            Err(VerifyError::Invalid),
+            verifier.verify_at(&headers, &body, "other", at(1_700_000_100))
+        );
+    }
+
+    #[test]
+    fn test_slack() {
+        let body = Bytes::from("token=x&team_id=T1");
+        let signature = sign_hex("slack", "v0:1700000000:token=x&team_id=T1");
+        let mut headers = headers("x-slack-signature", &format!("v0={}", signature));
+        headers.insert(
+            String::from("x-slack-request-timestamp"),
+            String::from("1700000000"),
+        );
+        let verifier = SlackVerifier::new(DEFAULT_TOLERANCE);
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(
+            Ok(()),
+            verifier.verify_at(&headers, &body, "slack", at(1_699_999_900))
+        );
+        assert_eq!(
+            Err(VerifyError::Expired),
+            verifier.verify_at(&headers, &body, "slack", at(1_699_999_000))
+        );
+    }
+
+    #[test]
+    fn test_ed25519() {
+        use ring::signature::KeyPair;
+
+        let pkcs8 =
+            signature::Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+        let key_pair = signature::Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = key_pair.public_key().as_ref();
+        let signed = key_pair.sign(br#"1700000000{"type": 1}"#);
+        let mut headers = headers("x-signature-ed25519", &hex::encode(signed.as_ref()));
+        headers.insert(
+            String::from("x-signature-timestamp"),
+            String::from("1700000000"),
+        );
+        let pem = format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            STANDARD.encode([&ED25519_SPKI_PREFIX[..], public_key].concat())
+        );
+        let verifier = Ed25519Verifier::new(DEFAULT_TOLERANCE);
+        let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let body = Bytes::from(r#"{"type": 1}"#);
+        for key in [hex::encode(public_key), pem] {
+            assert_eq!(Ok(()), verifier.verify_at(&headers, &body, &key, at));
+        }
+        let other = Bytes::from(r#"{"type": 2}"#);
+        assert_eq!(
+            Err(VerifyError::Invalid),
+            verifier.verify_at(&headers, &other, &hex::encode(public_key), at)
+        );
+    }
+}
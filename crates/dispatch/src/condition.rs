@@ -0,0 +1,181 @@
+use std::fmt;
+
+use mighty_hooks_config::BodyFormat;
+use prost_reflect::MessageDescriptor;
+
+use crate::{decode::decode_body, Incoming};
+
+#[derive(Debug)]
+pub enum ConditionErrors {
+    DeserializeBodyError(String),
+    ExpressionError(String),
+}
+
+impl fmt::Display for ConditionErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeserializeBodyError(message) => write!(f, "{}", message),
+            Self::ExpressionError(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Convert `&&`, `||` and `!` (outside of strings) to their tera equivalents
+fn to_tera_expression(expression: &str) -> String {
+    let mut converted = String::with_capacity(expression.len());
+    let mut chars = expression.chars().peekable();
+    let mut quote: Option<char> = None;
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) => {
+                converted.push(c);
+                if c == '\\' {
+                    if let Some(escaped) = chars.next() {
+                        converted.push(escaped);
+                    }
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' | '`' => {
+                    quote = Some(c);
+                    converted.push(c);
+                }
+                '&' if chars.peek() == Some(&'&') => {
+                    chars.next();
+                    converted.push_str(" and ");
+                }
+                '|' if chars.peek() == Some(&'|') => {
+                    chars.next();
+                    converted.push_str(" or ");
+                }
+                '!' if chars.peek() != Some(&'=') => converted.push_str(" not "),
+                _ => converted.push(c),
+            },
+        }
+    }
+    converted
+}
+
+/// Whether an expression uses the decoded body (`json`, `form` or `data`)
+fn uses_body(expression: &str) -> bool {
+    let mut chars = expression.chars().peekable();
+    let mut quote: Option<char> = None;
+    let mut previous = ' ';
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            if c == '\\' {
+                chars.next();
+            } else if c == q {
+                quote = None;
+            }
+        } else if matches!(c, '"' | '\'' | '`') {
+            quote = Some(c);
+        } else if c.is_alphabetic() || c == '_' {
+            let mut identifier = String::from(c);
+            while let Some(next) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                identifier.push(next);
+            }
+            // fields such as `headers.data` are not the body
+            if previous != '.' && matches!(identifier.as_str(), "json" | "form" | "data") {
+                return true;
+            }
+            previous = identifier.chars().last().unwrap_or(c);
+            continue;
+        }
+        if !c.is_whitespace() {
+            previous = c;
+        }
+    }
+    false
+}
+
+/// Build the context a target's condition is evaluated with,
+/// only decoding the body (as `format`, the Protobuf `message`, or by its content type)
+/// when the expression uses it so conditions on headers still work for any body
+pub fn build_context(
+    incoming: &Incoming,
+    expression: &str,
+    format: Option<BodyFormat>,
+    message: Option<&MessageDescriptor>,
+) -> Result<tera::Context, ConditionErrors> {
+    let mut context = tera::Context::new();
+    context.insert("headers", &incoming.headers);
+    context.insert("params", &incoming.params);
+    if uses_body(expression) {
+        let decoded = decode_body(&incoming.body, format, message)
+            .map_err(|err| ConditionErrors::DeserializeBodyError(err.to_string()))?;
+        context.insert("json", &decoded.json);
+        context.insert("form", &decoded.form);
+        context.insert("data", &decoded.data);
+    }
+    Ok(context)
+}
+
+/// Convert a `when` expression into a template that renders "true" when it is met
+pub fn to_template(expression: &str) -> String {
+    format!(
+        "{{% if {} %}}true{{% endif %}}",
+        to_tera_expression(expression)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mighty_hooks_core::Body;
+
+    use super::*;
+
+    fn incoming(event: &str, json: &str) -> Incoming {
+        Incoming {
+            hook_key: String::from("hooks.example.com/hello"),
+            params: HashMap::new(),
+            body: Body {
+                content: json.to_owned().into(),
+                content_type: String::from("application/json"),
+            },
+            headers: HashMap::from([(String::from("x-github-event"), event.to_owned())]),
+        }
+    }
+
+    #[test]
+    fn test_to_tera_expression() {
+        assert_eq!(
+            r#"a == "&&"  and   not b  or  c != 'x||y'"#,
+            to_tera_expression(r#"a == "&&" && !b || c != 'x||y'"#)
+        );
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let expression = r#"headers["x-github-event"] == "push" && json.ref == "refs/heads/main""#;
+        let evaluate_for = |event, json| {
+            let context = build_context(&incoming(event, json), expression, None, None).unwrap();
+            tera::Tera::one_off(&to_template(expression), &context, false).unwrap() == "true"
+        };
+        assert!(evaluate_for("push", r#"{"ref": "refs/heads/main"}"#));
+        assert!(!evaluate_for("push", r#"{"ref": "refs/heads/dev"}"#));
+        assert!(!evaluate_for("issues", r#"{"ref": "refs/heads/main"}"#));
+    }
+
+    #[test]
+    fn test_uses_body() {
+        assert!(uses_body("json.ref == 'main'"));
+        assert!(uses_body("!data"));
+        assert!(!uses_body(r#"headers["x-json"] == "data" && headers.form"#));
+        assert!(!uses_body("params.jsonp == 'x'"));
+    }
+
+    #[test]
+    fn test_build_context_undecodable() {
+        let incoming = incoming("push", "not json");
+        let header_only = r#"headers["x-github-event"] == "push""#;
+        assert!(build_context(&incoming, header_only, None, None).is_ok());
+        assert!(build_context(&incoming, "json.ref", None, None).is_err());
+        // decoded as the target's format
+        assert!(build_context(&incoming, "json.ref", Some(BodyFormat::Yaml), None).is_ok());
+    }
+}
@@ -0,0 +1,127 @@
+use std::{io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    queue::{now_ms, Delivery},
+    store::{JsonDir, JsonDirWriter},
+};
+
+/// A delivery that was given up on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// When it was given up on, in milliseconds since the unix epoch
+    pub failed_at: u64,
+    #[serde(flatten)]
+    pub delivery: Delivery,
+}
+
+/// Deliveries that could not be delivered,
+/// kept so they can be inspected and replayed
+pub struct DeadLetterStore {
+    dir: JsonDir,
+    writer: JsonDirWriter,
+}
+
+impl DeadLetterStore {
+    /// Open a store persisted in a directory
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = JsonDir::open(dir)?;
+        Ok(Self {
+            writer: JsonDirWriter::spawn(dir.clone(), "dead-letters")?,
+            dir,
+        })
+    }
+
+    /// Add a delivery to the store, written in the background
+    pub fn push(&self, delivery: Delivery) {
+        let dead_letter = DeadLetter {
+            failed_at: now_ms(),
+            delivery,
+        };
+        self.writer.write(&dead_letter.delivery.id, &dead_letter)
+    }
+
+    /// Get all stored deliveries, oldest first
+    pub fn list(&self) -> io::Result<Vec<DeadLetter>> {
+        let mut dead_letters = self.dir.read_all::<DeadLetter>()?;
+        dead_letters.sort_by_key(|dead_letter| dead_letter.failed_at);
+        Ok(dead_letters)
+    }
+
+    /// Get a stored delivery by its id
+    pub fn get(&self, id: &str) -> io::Result<DeadLetter> {
+        self.dir.read(id)
+    }
+
+    /// Remove a stored delivery, returning whether it existed
+    pub fn remove(&self, id: &str) -> io::Result<bool> {
+        self.dir.remove(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mighty_hooks_config::{RetryPolicy, SignScheme};
+
+    use super::*;
+    use crate::signer::Target;
+
+    fn delivery(id: &str) -> Delivery {
+        Delivery {
+            id: id.to_owned(),
+            hook: String::from("hooks.example.com/hello"),
+            href: String::from("http://example.com/hello"),
+            content_type: String::from("application/octet-stream"),
+            body: vec![0, 159, 146, 150],
+            headers: HashMap::from([(String::from("x-test"), String::from("test"))]),
+            target: Some(Target {
+                index: 0,
+                href: String::from("http://example.com/hello"),
+                sign: SignScheme::StandardWebhooks,
+            }),
+            retry: RetryPolicy::default(),
+            accept_status: vec![200],
+            attempts: 3,
+            next_attempt_at: 0,
+            last_error: Some(String::from("target responded with 500")),
+        }
+    }
+
+    #[test]
+    fn test_dead_letters() {
+        let path =
+            std::env::temp_dir().join(format!("mighty-hooks-deadletter-{}", std::process::id()));
+        let store = DeadLetterStore::open(&path).unwrap();
+        store.push(delivery("first"));
+        store.push(delivery("second"));
+        // wait for the dead letters to be written
+        drop(store);
+        std::fs::write(path.join("corrupt.json"), "not json").unwrap();
+        let store = DeadLetterStore::open(&path).unwrap();
+        let mut ids: Vec<String> = store
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|dead_letter| dead_letter.delivery.id)
+            .collect();
+        // both may have failed in the same millisecond
+        ids.sort();
+        assert_eq!(vec!["first", "second"], ids);
+        // a replay sends the delivery as it was first sent
+        let dead_letter = store.get("first").unwrap();
+        let to_dispatch = dead_letter.delivery.to_dispatch();
+        let expected = delivery("first");
+        assert_eq!(expected.href, to_dispatch.href);
+        assert_eq!(expected.body, to_dispatch.body.content.to_vec());
+        assert_eq!(expected.content_type, to_dispatch.body.content_type);
+        assert_eq!(expected.headers, to_dispatch.headers);
+        assert_eq!((expected.hook, expected.target), (to_dispatch.hook, to_dispatch.target));
+        assert!(store.remove("first").unwrap());
+        assert!(store.get("first").is_err());
+        assert!(store.get("corrupt").is_err());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
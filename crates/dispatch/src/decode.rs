@@ -0,0 +1,408 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mighty_hooks_config::BodyFormat;
+use mighty_hooks_core::{media, Body};
+use prost_reflect::{DynamicMessage, MessageDescriptor};
+use serde_json::{Map, Number, Value};
+
+/// A body that could not be decoded as its format
+#[derive(Debug)]
+pub struct DecodeError(pub String);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Structured values decoded from a body, depending on its format
+#[derive(Debug, Default)]
+pub struct DecodedBody {
+    pub json: Option<Value>,
+    /// Fields of a form, repeated fields are collected into a list
+    pub form: Option<Map<String, Value>>,
+    /// The decoded body, whichever format it was
+    pub data: Option<Value>,
+}
+
+/// Get the format of a body from its content type
+pub fn detect_format(content_type: &str) -> Option<BodyFormat> {
+    if media::is_json(content_type) {
+        Some(BodyFormat::Json)
+    } else if media::is_form(content_type) {
+        Some(BodyFormat::Form)
+    } else if media::is_yaml(content_type) {
+        Some(BodyFormat::Yaml)
+    } else if media::is_toml(content_type) {
+        Some(BodyFormat::Toml)
+    } else if media::is_xml(content_type) {
+        Some(BodyFormat::Xml)
+    } else if media::is_msgpack(content_type) {
+        Some(BodyFormat::Msgpack)
+    } else if media::is_cbor(content_type) {
+        Some(BodyFormat::Cbor)
+    } else {
+        None
+    }
+}
+
+fn text(content: &[u8]) -> Result<&str, DecodeError> {
+    std::str::from_utf8(content).map_err(|_| DecodeError(String::from("body must be text")))
+}
+
+/// Add a value under a key, collecting repeated keys into a list
+fn insert_repeated(map: &mut Map<String, Value>, key: &str, value: Value) {
+    match map.get_mut(key) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+        None => {
+            map.insert(key.to_owned(), value);
+        }
+    }
+}
+
+/// Convert an XML element, attributes become `@name` and text `#text`,
+/// an element with only text becomes that text
+fn xml_element(node: roxmltree::Node) -> Value {
+    let mut map = Map::new();
+    for attribute in node.attributes() {
+        map.insert(
+            format!("@{}", attribute.name()),
+            Value::String(attribute.value().to_owned()),
+        );
+    }
+    let mut text = String::new();
+    for child in node.children() {
+        if child.is_element() {
+            insert_repeated(&mut map, child.tag_name().name(), xml_element(child));
+        } else if child.is_text() {
+            text.push_str(child.text().unwrap_or_default());
+        }
+    }
+    let text = text.trim();
+    match (map.is_empty(), text.is_empty()) {
+        (true, _) => Value::String(text.to_owned()),
+        (false, true) => Value::Object(map),
+        (false, false) => {
+            map.insert(String::from("#text"), Value::String(text.to_owned()));
+            Value::Object(map)
+        }
+    }
+}
+
+fn decode_xml(content: &[u8]) -> Result<Value, DecodeError> {
+    let document = roxmltree::Document::parse(text(content)?)
+        .map_err(|err| DecodeError(format!("body is not valid XML: {}", err)))?;
+    let root = document.root_element();
+    let mut map = Map::new();
+    map.insert(root.tag_name().name().to_owned(), xml_element(root));
+    Ok(Value::Object(map))
+}
+
+fn integer(value: i128) -> Value {
+    if let Ok(value) = i64::try_from(value) {
+        Value::Number(Number::from(value))
+    } else if let Ok(value) = u64::try_from(value) {
+        Value::Number(Number::from(value))
+    } else {
+        Value::String(value.to_string())
+    }
+}
+
+fn float(value: f64) -> Value {
+    Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
+
+/// Use a decoded map key as an object key, keys that are not strings are converted to text
+fn object_key(key: Value) -> String {
+    match key {
+        Value::String(key) => key,
+        key => key.to_string(),
+    }
+}
+
+/// Convert a MessagePack value, binary data becomes base64
+fn msgpack_value(value: rmpv::Value) -> Value {
+    match value {
+        rmpv::Value::Nil => Value::Null,
+        rmpv::Value::Boolean(value) => Value::Bool(value),
+        rmpv::Value::Integer(value) => match value.as_i64() {
+            Some(value) => integer(value.into()),
+            None => integer(value.as_u64().unwrap_or_default().into()),
+        },
+        rmpv::Value::F32(value) => float(value.into()),
+        rmpv::Value::F64(value) => float(value),
+        rmpv::Value::String(value) => match value.into_str() {
+            Some(value) => Value::String(value),
+            None => Value::Null,
+        },
+        rmpv::Value::Binary(value) | rmpv::Value::Ext(_, value) => {
+            Value::String(STANDARD.encode(value))
+        }
+        rmpv::Value::Array(values) => Value::Array(values.into_iter().map(msgpack_value).collect()),
+        rmpv::Value::Map(entries) => Value::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (object_key(msgpack_value(key)), msgpack_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Convert a CBOR value, byte strings become base64 and tags are dropped
+fn cbor_value(value: ciborium::value::Value) -> Value {
+    use ciborium::value::Value as Cbor;
+    match value {
+        Cbor::Integer(value) => integer(value.into()),
+        Cbor::Bytes(value) => Value::String(STANDARD.encode(value)),
+        Cbor::Float(value) => float(value),
+        Cbor::Text(value) => Value::String(value),
+        Cbor::Bool(value) => Value::Bool(value),
+        Cbor::Tag(_, value) => cbor_value(*value),
+        Cbor::Array(values) => Value::Array(values.into_iter().map(cbor_value).collect()),
+        Cbor::Map(entries) => Value::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (object_key(cbor_value(key)), cbor_value(value)))
+                .collect(),
+        ),
+        _ => Value::Null,
+    }
+}
+
+/// Decode a body as a format, or by its content type if not given,
+/// unwrapping the JSON in a form's `payload` field (as GitHub sends it);
+/// bodies are decoded as the Protobuf `message` when given, unless another format is
+pub fn decode_body(
+    body: &Body,
+    format: Option<BodyFormat>,
+    message: Option<&MessageDescriptor>,
+) -> Result<DecodedBody, DecodeError> {
+    let mut decoded = DecodedBody::default();
+    let format = format
+        .or(message.map(|_| BodyFormat::Protobuf))
+        .or_else(|| detect_format(&body.content_type));
+    let format = match format {
+        Some(format) => format,
+        None => return Ok(decoded),
+    };
+    match format {
+        BodyFormat::Json => {
+            let json: Value = serde_json::from_slice(&body.content)
+                .map_err(|err| DecodeError(format!("body is not valid JSON: {}", err)))?;
+            decoded.data = Some(json.clone());
+            decoded.json = Some(json);
+        }
+        BodyFormat::Form => {
+            let fields: Vec<(String, String)> = serde_urlencoded::from_bytes(&body.content)
+                .map_err(|err| DecodeError(format!("body is not a valid form: {}", err)))?;
+            let mut form = Map::new();
+            for (key, value) in fields {
+                insert_repeated(&mut form, &key, Value::String(value));
+            }
+            if let Some(Value::String(payload)) = form.get("payload") {
+                decoded.json = serde_json::from_str(payload).ok();
+            }
+            decoded.data = Some(Value::Object(form.clone()));
+            decoded.form = Some(form);
+        }
+        BodyFormat::Yaml => {
+            decoded.data = Some(
+                serde_yaml::from_slice(&body.content)
+                    .map_err(|err| DecodeError(format!("body is not valid YAML: {}", err)))?,
+            );
+        }
+        BodyFormat::Toml => {
+            decoded.data = Some(
+                toml::from_str(text(&body.content)?)
+                    .map_err(|err| DecodeError(format!("body is not valid TOML: {}", err)))?,
+            );
+        }
+        BodyFormat::Xml => decoded.data = Some(decode_xml(&body.content)?),
+        BodyFormat::Msgpack => {
+            let value = rmpv::decode::read_value(&mut &body.content[..])
+                .map_err(|err| DecodeError(format!("body is not valid MessagePack: {}", err)))?;
+            decoded.data = Some(msgpack_value(value));
+        }
+        BodyFormat::Cbor => {
+            let value: ciborium::value::Value = ciborium::de::from_reader(&body.content[..])
+                .map_err(|err| DecodeError(format!("body is not valid CBOR: {}", err)))?;
+            decoded.data = Some(cbor_value(value));
+        }
+        BodyFormat::Protobuf => {
+            let message = message
+                .ok_or_else(|| DecodeError(String::from("no protobuf message to decode as")))?;
+            let message = DynamicMessage::decode(message.clone(), body.content.clone())
+                .map_err(|err| DecodeError(format!("body is not a valid protobuf: {}", err)))?;
+            decoded.data = Some(
+                serde_json::to_value(&message)
+                    .map_err(|err| DecodeError(format!("cannot convert protobuf: {}", err)))?,
+            );
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn body(content_type: &str, content: &str) -> Body {
+        binary_body(content_type, content.as_bytes())
+    }
+
+    fn binary_body(content_type: &str, content: &[u8]) -> Body {
+        Body {
+            content: content.to_vec().into(),
+            content_type: content_type.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_form() {
+        let decoded = decode_body(
+            &body(
+                "application/x-www-form-urlencoded",
+                "command=%2Fdeploy&text=main+now",
+            ),
+            None,
+            None,
+        )
+        .unwrap();
+        let form = decoded.form.unwrap();
+        assert_eq!("/deploy", form["command"]);
+        assert_eq!("main now", form["text"]);
+        assert_eq!(None, decoded.json);
+        // repeated fields are all kept
+        let decoded = decode_body(
+            &body("application/x-www-form-urlencoded", "tag=a&tag=b&name=c"),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(Some(json!({"tag": ["a", "b"], "name": "c"})), decoded.data);
+    }
+
+    #[test]
+    fn test_form_payload() {
+        let decoded = decode_body(
+            &body(
+                "application/x-www-form-urlencoded",
+                "payload=%7B%22ref%22%3A%22refs%2Fheads%2Fmain%22%7D",
+            ),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(Some(json!({"ref": "refs/heads/main"})), decoded.json);
+    }
+
+    #[test]
+    fn test_yaml_toml() {
+        let decoded =
+            decode_body(&body("application/yaml", "ref: main\nsize: 2"), None, None).unwrap();
+        assert_eq!(Some(json!({"ref": "main", "size": 2})), decoded.data);
+        assert_eq!(None, decoded.json);
+        let decoded = decode_body(&body("application/toml", "ref = \"main\""), None, None).unwrap();
+        assert_eq!(Some(json!({"ref": "main"})), decoded.data);
+    }
+
+    #[test]
+    fn test_xml() {
+        let content = r#"<?xml version="1.0"?>
+            <entry xmlns="http://www.w3.org/2005/Atom">
+                <title>Released</title>
+                <link rel="alternate" href="https://example.com/1"/>
+                <category term="a"/>
+                <category term="b">B</category>
+            </entry>"#;
+        let decoded = decode_body(&body("application/atom+xml", content), None, None).unwrap();
+        assert_eq!(
+            Some(json!({"entry": {
+                "title": "Released",
+                "link": {"@rel": "alternate", "@href": "https://example.com/1"},
+                "category": [{"@term": "a"}, {"@term": "b", "#text": "B"}],
+            }})),
+            decoded.data
+        );
+    }
+
+    #[test]
+    fn test_forced_format() {
+        let decoded = decode_body(
+            &body("text/plain", "ref: main"),
+            Some(BodyFormat::Yaml),
+            None,
+        );
+        assert_eq!(Some(json!({"ref": "main"})), decoded.unwrap().data);
+        assert!(decode_body(&body("text/plain", "<a"), Some(BodyFormat::Xml), None).is_err());
+        assert!(decode_body(&body("text/plain", "<a"), None, None)
+            .unwrap()
+            .data
+            .is_none());
+    }
+
+    #[test]
+    fn test_binary() {
+        let expected = Some(json!({"id": 1, "raw": "AQI="}));
+        // {"id": 1, "raw": <bytes 01 02>}
+        let msgpack = b"\x82\xa2id\x01\xa3raw\xc4\x02\x01\x02";
+        let decoded = decode_body(&binary_body("application/msgpack", msgpack), None, None);
+        assert_eq!(expected, decoded.unwrap().data);
+        let cbor = b"\xa2\x62id\x01\x63raw\x42\x01\x02";
+        let decoded = decode_body(&binary_body("application/cbor", cbor), None, None);
+        assert_eq!(expected, decoded.unwrap().data);
+    }
+
+    #[test]
+    fn test_protobuf() {
+        use prost_reflect::{
+            prost_types::{
+                field_descriptor_proto::Type, DescriptorProto, FieldDescriptorProto,
+                FileDescriptorProto, FileDescriptorSet,
+            },
+            DescriptorPool,
+        };
+
+        let field = |name: &str, number, r#type: Type| FieldDescriptorProto {
+            name: Some(name.to_owned()),
+            number: Some(number),
+            r#type: Some(r#type as i32),
+            json_name: Some(name.to_owned()),
+            ..Default::default()
+        };
+        let pool = DescriptorPool::from_file_descriptor_set(FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                name: Some(String::from("reading.proto")),
+                package: Some(String::from("example")),
+                syntax: Some(String::from("proto3")),
+                message_type: vec![DescriptorProto {
+                    name: Some(String::from("Reading")),
+                    field: vec![
+                        field("sensor", 1, Type::String),
+                        field("value", 2, Type::Int32),
+                    ],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        })
+        .unwrap();
+        let message = pool.get_message_by_name("example.Reading").unwrap();
+        // sensor: "a1", value: 7
+        let content = b"\x0a\x02a1\x10\x07";
+        let decoded = decode_body(
+            &binary_body("application/octet-stream", content),
+            None,
+            Some(&message),
+        );
+        assert_eq!(
+            Some(json!({"sensor": "a1", "value": 7})),
+            decoded.unwrap().data
+        );
+        assert!(decode_body(&body("text/plain", ""), Some(BodyFormat::Protobuf), None).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::{collections::HashMap, fmt, fs};
+
+use mighty_hooks_config::{Config, ProtobufMessage};
+use prost_reflect::{DescriptorPool, MessageDescriptor};
+
+/// A Protobuf message in the config that could not be loaded
+#[derive(Debug)]
+pub struct DecoderError {
+    /// Key of the hook the message belongs to
+    pub hook: String,
+    /// Where the message is in the hook, e.g. `out[0].reword.protobuf`
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for DecoderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid protobuf message in hook \"{}\" at {}: {}",
+            self.hook, self.field, self.message
+        )
+    }
+}
+
+/// Load a Protobuf message from its descriptor file
+fn load_message(protobuf: &ProtobufMessage) -> Result<MessageDescriptor, String> {
+    let bytes = fs::read(&protobuf.descriptor)
+        .map_err(|err| format!("cannot read \"{}\": {}", protobuf.descriptor, err))?;
+    let pool = DescriptorPool::decode(bytes.as_slice()).map_err(|err| {
+        format!(
+            "\"{}\" is not a protobuf descriptor set: {}",
+            protobuf.descriptor, err
+        )
+    })?;
+    pool.get_message_by_name(&protobuf.message).ok_or_else(|| {
+        format!(
+            "no message \"{}\" in \"{}\"",
+            protobuf.message, protobuf.descriptor
+        )
+    })
+}
+
+/// The Protobuf messages bodies are decoded as, by hook key and target index,
+/// loaded once at startup
+#[derive(Debug, Default)]
+pub struct Decoders {
+    messages: HashMap<(String, usize), MessageDescriptor>,
+}
+
+impl Decoders {
+    /// Load every Protobuf message in the config, returning every one that failed
+    pub fn from_config(config: &Config) -> Result<Self, Vec<DecoderError>> {
+        let mut messages = HashMap::new();
+        let mut errors = Vec::new();
+        for (hook_key, hook) in &config.hooks {
+            for (index, out) in hook.out.iter().enumerate() {
+                let protobuf = out
+                    .reword
+                    .as_ref()
+                    .and_then(|reword| reword.protobuf.as_ref());
+                if let Some(protobuf) = protobuf {
+                    match load_message(protobuf) {
+                        Ok(message) => {
+                            messages.insert((hook_key.clone(), index), message);
+                        }
+                        Err(message) => errors.push(DecoderError {
+                            hook: hook_key.clone(),
+                            field: format!("out[{}].reword.protobuf", index),
+                            message,
+                        }),
+                    }
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(Self { messages }),
+            false => Err(errors),
+        }
+    }
+
+    /// Get the Protobuf message a target decodes bodies as
+    pub(crate) fn message(&self, hook_key: &str, index: usize) -> Option<&MessageDescriptor> {
+        self.messages.get(&(hook_key.to_owned(), index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config_protobuf() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+host: 0.0.0.0
+port: 8000
+hooks:
+  hooks.example.com/hello:
+    in:
+      content_type: application/x-protobuf
+    out:
+      - href: http://internal.example.com/hello
+        reword:
+          content: "{{ content.data }}"
+          protobuf:
+            descriptor: /does/not/exist.desc
+            message: example.Reading
+"#,
+        )
+        .unwrap();
+        let errors = Decoders::from_config(&config).unwrap_err();
+        assert_eq!("hooks.example.com/hello", errors[0].hook);
+        assert_eq!("out[0].reword.protobuf", errors[0].field);
+    }
+}
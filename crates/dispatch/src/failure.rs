@@ -0,0 +1,158 @@
+use std::{fmt, time::Duration, time::SystemTime};
+
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+
+/// Why an attempt to deliver a webhook failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeliveryError {
+    /// Could not connect to the target
+    Connect(String),
+    /// Target did not respond in time
+    Timeout,
+    /// Target is rate limiting, optionally saying when to try again
+    RateLimited(Option<Duration>),
+    /// Target rejected the request with a 4xx status
+    ClientError(StatusCode),
+    /// Target failed to handle the request with a 5xx status
+    ServerError(StatusCode),
+    /// Target responded with a status not in the accepted list
+    UnexpectedStatus(StatusCode),
+    /// Any other failure while making the request
+    Request(String),
+    /// Target was changed or removed from the config since the delivery was made
+    TargetChanged,
+}
+
+impl DeliveryError {
+    /// Classify a failed request
+    pub fn from_reqwest(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout
+        } else if err.is_connect() {
+            Self::Connect(err.to_string())
+        } else {
+            Self::Request(err.to_string())
+        }
+    }
+
+    /// Classify a response that has an unaccepted status
+    pub fn from_response(response: &Response) -> Self {
+        let status = response.status();
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited(
+                response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_retry_after),
+            ),
+            _ if status.is_client_error() => Self::ClientError(status),
+            _ if status.is_server_error() => Self::ServerError(status),
+            _ => Self::UnexpectedStatus(status),
+        }
+    }
+
+    /// Short name of the failure class, used for metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Connect(_) => "connect",
+            Self::Timeout => "timeout",
+            Self::RateLimited(_) => "rate_limited",
+            Self::ClientError(_) => "4xx",
+            Self::ServerError(_) => "5xx",
+            Self::UnexpectedStatus(_) => "unexpected_status",
+            Self::Request(_) => "request",
+            Self::TargetChanged => "target_changed",
+        }
+    }
+
+    /// Whether trying again could succeed,
+    /// a 4xx (other than 408 & 429) means the request itself is wrong
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::ClientError(status) => *status == StatusCode::REQUEST_TIMEOUT,
+            Self::UnexpectedStatus(_) | Self::TargetChanged => false,
+            _ => true,
+        }
+    }
+
+    /// How long the target asked us to wait before trying again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited(retry_after) => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(err) => write!(f, "connect error: {}", err),
+            Self::Timeout => write!(f, "timed out"),
+            Self::RateLimited(Some(retry_after)) => {
+                write!(f, "rate limited, retry after {:?}", retry_after)
+            }
+            Self::RateLimited(None) => write!(f, "rate limited"),
+            Self::ClientError(status) | Self::ServerError(status) => {
+                write!(f, "rejected with status {}", status)
+            }
+            Self::UnexpectedStatus(status) => write!(f, "unexpected status {}", status),
+            Self::Request(err) => write!(f, "request error: {}", err),
+            Self::TargetChanged => write!(f, "target is no longer in the config"),
+        }
+    }
+}
+
+/// Parse a Retry-After header value, given as either seconds or a http date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(value.trim())
+            .ok()
+            .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default()),
+    }
+}
+
+/// Whether a response status counts as a successful delivery,
+/// when no accepted statuses are given any 2xx is accepted
+pub fn is_accepted(status: StatusCode, accept_status: &[u16]) -> bool {
+    match accept_status.is_empty() {
+        true => status.is_success(),
+        false => accept_status.contains(&status.as_u16()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_accepted() {
+        assert!(is_accepted(StatusCode::OK, &[]));
+        assert!(is_accepted(StatusCode::NO_CONTENT, &[]));
+        assert!(!is_accepted(StatusCode::NOT_FOUND, &[]));
+        assert!(!is_accepted(StatusCode::MOVED_PERMANENTLY, &[]));
+        assert!(is_accepted(StatusCode::NOT_FOUND, &[200, 404]));
+        assert!(!is_accepted(StatusCode::NO_CONTENT, &[200]));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(Some(Duration::from_secs(120)), parse_retry_after("120"));
+        assert_eq!(
+            Some(Duration::ZERO),
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT")
+        );
+        assert_eq!(None, parse_retry_after("soon"));
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(DeliveryError::Timeout.is_retryable());
+        assert!(DeliveryError::ServerError(StatusCode::BAD_GATEWAY).is_retryable());
+        assert!(DeliveryError::RateLimited(None).is_retryable());
+        assert!(DeliveryError::ClientError(StatusCode::REQUEST_TIMEOUT).is_retryable());
+        assert!(!DeliveryError::ClientError(StatusCode::NOT_FOUND).is_retryable());
+    }
+}
@@ -1,14 +1,35 @@
-use futures::future::join_all;
-use mighty_hooks_core::Body;
-use std::collections::HashMap;
+use futures::{future::join_all, stream::FuturesUnordered, Future, StreamExt};
+use mighty_hooks_core::{metrics::Metrics, Body};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use mighty_hooks_config::HookOut;
+use mighty_hooks_config::{HookAck, HookOut, SignScheme};
 use reqwest::{
-    header::{HeaderMap, HeaderName},
+    header::{HeaderMap, HeaderName, HeaderValue},
     redirect::Policy,
 };
+use reword::RewordErrors;
+use templates::template_name;
 
+pub use deadletter::{DeadLetter, DeadLetterStore};
+pub use decoders::{DecoderError, Decoders};
+pub use failure::DeliveryError;
+pub use queue::{Delivery, DeliveryQueue};
+pub use replay::ReplayCache;
+pub use signer::{Signers, Target};
+pub use templates::{TemplateError, Templates};
+
+mod condition;
+mod deadletter;
+mod decode;
+mod decoders;
+mod failure;
+mod queue;
+mod replay;
+mod retry;
 mod reword;
+mod signer;
+mod store;
+mod templates;
 
 static USER_AGENT: &str = concat!(
     "MightyHooks/",
@@ -16,131 +37,641 @@ static USER_AGENT: &str = concat!(
     " (+https://github.com/enchant97/mighty-hooks)"
 );
 
-fn headers_convert(headers: &HashMap<String, String>) -> HeaderMap {
+/// How long to wait for a target to respond
+static REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn headers_convert(headers: &HashMap<String, String>) -> Result<HeaderMap, DeliveryError> {
     let mut header_map = HeaderMap::new();
     for (key, value) in headers {
-        header_map.insert::<HeaderName>(key.parse::<HeaderName>().unwrap(), value.parse().unwrap());
+        let invalid = || DeliveryError::Request(format!("invalid header \"{}\"", key));
+        let name = key.parse::<HeaderName>().map_err(|_| invalid())?;
+        let value = value.parse::<HeaderValue>().map_err(|_| invalid())?;
+        header_map.insert(name, value);
     }
-    header_map
+    Ok(header_map)
+}
+
+/// A received webhook, to be sent to targets
+#[derive(Debug, Clone)]
+pub struct Incoming {
+    /// Key of the hook that received the webhook
+    pub hook_key: String,
+    /// Values captured by parameters in the hook key
+    pub params: HashMap<String, String>,
+    pub body: Body,
+    pub headers: HashMap<String, String>,
+}
+
+/// Replace `{name}` placeholders with captured parameters
+fn fill_params(value: &str, params: &HashMap<String, String>) -> String {
+    params.iter().fold(value.to_owned(), |value, (name, param)| {
+        value.replace(&format!("{{{}}}", name), param)
+    })
 }
 
 struct ToDispatch {
     pub href: String,
     pub body: Body,
     pub headers: HashMap<String, String>,
+    /// Key of the hook that received the webhook
+    pub hook: String,
+    /// Target in the hook, if known
+    pub target: Option<Target>,
+}
+
+/// A first attempt that failed, holding the delivery if it can be retried
+type FailedAttempt = Option<(Delivery, DeliveryError)>;
+
+/// How a webhook was handled, deciding the response to the sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Acknowledgement {
+    /// Received as the hook's `ack` asks, any failures are retried or recorded here
+    Received,
+    /// Not received, but the sender trying again could succeed
+    Failed,
+    /// Not received, and trying again will not succeed
+    Rejected,
 }
 
 pub struct Dispatcher {
     client: reqwest::Client,
+    queue: DeliveryQueue,
+    dead_letters: Option<DeadLetterStore>,
+    templates: Templates,
+    decoders: Decoders,
+    signers: Signers,
+    metrics: Arc<Metrics>,
 }
 
 impl Dispatcher {
-    pub fn new() -> Self {
+    pub fn new(
+        queue: DeliveryQueue,
+        dead_letters: Option<DeadLetterStore>,
+        templates: Templates,
+        decoders: Decoders,
+        signers: Signers,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         Self {
             client: reqwest::Client::builder()
                 .user_agent(USER_AGENT)
                 .redirect(Policy::none())
+                .timeout(REQUEST_TIMEOUT)
                 .build()
                 .expect("failed to build client for webhook dispatcher"),
+            queue,
+            dead_letters,
+            templates,
+            decoders,
+            signers,
+            metrics,
         }
     }
 
-    async fn dispatch(&self, to_dispatch: ToDispatch) {
+    /// Sign a request for this attempt and send it
+    async fn send(
+        &self,
+        to_dispatch: &ToDispatch,
+        accept_status: &[u16],
+    ) -> Result<(), DeliveryError> {
+        let mut headers = to_dispatch.headers.clone();
+        if let Some(target) = &to_dispatch.target {
+            self.signers.sign(&to_dispatch.hook, target, &to_dispatch.body, &mut headers)?;
+        }
         match self
             .client
             .post(to_dispatch.href.clone())
-            .body(to_dispatch.body.content)
-            .headers(headers_convert(&to_dispatch.headers))
+            .body(to_dispatch.body.content.clone())
+            .headers(headers_convert(&headers)?)
             .send()
             .await
         {
-            Ok(_) => log::info!("dispatched webhook to {}", to_dispatch.href),
-            Err(_) => log::error!("failed to dispatch webhook to {}", to_dispatch.href),
+            Ok(response) => match failure::is_accepted(response.status(), accept_status) {
+                true => Ok(()),
+                false => Err(DeliveryError::from_response(&response)),
+            },
+            Err(err) => Err(DeliveryError::from_reqwest(err)),
         }
     }
 
-    pub async fn dispatch_hook(
+    async fn dispatch(
         &self,
-        hook: &HookOut,
-        body: Body,
-        extra_headers: HashMap<String, String>,
-    ) {
-        let mut headers: HashMap<String, String> = HashMap::new();
-        headers.extend(extra_headers);
-        // sign the body if a secret is set
-        if let Some(secret) = &hook.secret_256 {
-            let signature = mighty_hooks_core::signing::sign_hmac_sha256(secret, &body.content);
-            headers.insert(
-                "X-Hub-Signature-256".to_string(),
-                format!("sha256={}", signature),
+        to_dispatch: &ToDispatch,
+        accept_status: &[u16],
+    ) -> Result<(), DeliveryError> {
+        let result = self.send(to_dispatch, accept_status).await;
+        match &result {
+            Ok(()) => {
+                log::info!("dispatched webhook to {}", to_dispatch.href);
+                self.metrics
+                    .inc("mighty_hooks_deliveries_total", &[("result", "success")]);
+            }
+            Err(err) => {
+                log::error!("failed to dispatch webhook to {}: {}", to_dispatch.href, err);
+                self.metrics
+                    .inc("mighty_hooks_deliveries_total", &[("result", err.kind())]);
+            }
+        }
+        result
+    }
+
+    /// Record a failed attempt,
+    /// queueing the delivery again if the retry policy allows it
+    fn failed_attempt(&self, mut delivery: Delivery, error: DeliveryError) {
+        delivery.attempts += 1;
+        delivery.last_error = Some(error.to_string());
+        if !error.is_retryable() {
+            log::error!(
+                "not retrying webhook to {} as it was rejected: {}",
+                delivery.href,
+                error
             );
+            self.give_up(delivery);
+            return;
+        }
+        if delivery.attempts >= delivery.retry.max_attempts {
+            log::error!(
+                "giving up on webhook to {} after {} attempts",
+                delivery.href,
+                delivery.attempts
+            );
+            self.give_up(delivery);
+            return;
+        }
+        // wait for at least as long as the target asked for, up to the longest delay allowed
+        let retry_after = error.retry_after().unwrap_or_default();
+        let delay = retry::backoff_delay(&delivery.retry, delivery.attempts)
+            .max(retry_after.min(Duration::from_millis(delivery.retry.max_delay_ms)));
+        delivery.next_attempt_at = queue::now_ms() + delay.as_millis() as u64;
+        log::warn!(
+            "retrying webhook to {} in {:?} (attempt {} of {})",
+            delivery.href,
+            delay,
+            delivery.attempts + 1,
+            delivery.retry.max_attempts
+        );
+        self.queue.push(delivery);
+    }
+
+    /// Stop retrying a delivery, moving it to the dead letter store if there is one
+    fn give_up(&self, delivery: Delivery) {
+        self.metrics.inc("mighty_hooks_deliveries_dropped_total", &[]);
+        self.queue.remove(&delivery.id);
+        if let Some(dead_letters) = &self.dead_letters {
+            log::info!("moving delivery {} to dead letter store", delivery.id);
+            dead_letters.push(delivery);
+        }
+    }
+
+    /// Make a single attempt at sending a previously failed delivery
+    pub async fn replay(&self, delivery: &Delivery) -> Result<(), DeliveryError> {
+        self.dispatch(&delivery.to_dispatch(), &delivery.accept_status)
+            .await
+    }
+
+    async fn retry(&self, delivery: Delivery) {
+        match self
+            .dispatch(&delivery.to_dispatch(), &delivery.accept_status)
+            .await
+        {
+            Ok(()) => self.queue.remove(&delivery.id),
+            Err(err) => self.failed_attempt(delivery, err),
         }
-        // reword the body if needed
-        let to_dispatch = match &hook.reword {
-            Some(reword) => {
-                // reword body and set the new content type
-                // set the new content type
-                // or use the original content type
-                let reword_content_type = reword
-                    .content_type
-                    .clone()
-                    .unwrap_or_else(|| body.content_type.clone());
-                headers.insert("Content-Type".to_string(), reword_content_type.clone());
-                // reword the body
-                let reworded_body = match reword::reword_body(reword, &body, &headers) {
-                    Ok(v) => v,
-                    Err(err) => {
-                        log::error!("failed to reword body: {:?}", err);
-                        return;
+    }
+
+    /// Retry queued deliveries as they become due, never returns
+    pub async fn run_retries(&self) {
+        loop {
+            join_all(
+                self.queue
+                    .due(queue::now_ms())
+                    .into_iter()
+                    .map(|delivery| self.retry(delivery)),
+            )
+            .await;
+            match self.queue.next_due() {
+                Some(next_due) => {
+                    let wait = Duration::from_millis(next_due.saturating_sub(queue::now_ms()));
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {},
+                        _ = self.queue.changed() => {},
                     }
-                };
-                // reword headers
-                headers.extend(reword.set_headers.clone());
-                ToDispatch {
-                    href: hook.href.clone(),
-                    body: Body {
-                        content: reworded_body.into(),
-                        content_type: reword_content_type,
-                    },
-                    headers,
                 }
+                None => self.queue.changed().await,
+            }
+        }
+    }
+
+    /// Start retrying queued deliveries in the background
+    pub fn spawn_retries(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move { self.run_retries().await })
+    }
+
+    /// Prepare the request for a target, rewording it if needed
+    fn prepare(
+        &self,
+        hook: &HookOut,
+        index: usize,
+        incoming: &Incoming,
+    ) -> Result<ToDispatch, RewordErrors> {
+        let name = |field: &str| template_name(&incoming.hook_key, index, field);
+        let mut body = incoming.body.clone();
+        // only keep the headers asked for
+        let mut headers: HashMap<String, String> = HashMap::new();
+        for key in &hook.keep_headers {
+            if let Some(value) = incoming.headers.get(&key.to_lowercase()) {
+                headers.insert(key.clone(), value.clone());
             }
-            None => {
-                // no rewording, just use the original body and content type
-                headers.insert("Content-Type".to_string(), body.content_type.clone());
-                ToDispatch {
-                    href: hook.href.clone(),
-                    body,
-                    headers,
+        }
+        // the body is signed when sent, a Standard Webhooks message keeps its id between attempts
+        if hook.secret_256.is_some() && hook.sign == SignScheme::StandardWebhooks {
+            headers.insert(signer::WEBHOOK_ID.to_string(), signer::new_webhook_id());
+        }
+        // set the new content type or use the original content type
+        let content_type = hook
+            .reword
+            .as_ref()
+            .and_then(|reword| reword.content_type.clone())
+            .unwrap_or_else(|| body.content_type.clone());
+        headers.insert("Content-Type".to_string(), content_type.clone());
+        // only render templates when used, as not every body can be used in the context
+        let uses_templates = self.templates.has(&name("href"))
+            || hook.reword.as_ref().is_some_and(|reword| {
+                self.templates.has(&name("reword.content"))
+                    || reword.set_headers.keys().any(|key| {
+                        self.templates
+                            .has(&name(&format!("reword.set_headers.{}", key)))
+                    })
+            });
+        let context = match uses_templates {
+            true => {
+                let format = hook.reword.as_ref().and_then(|reword| reword.decode);
+                let message = self.decoders.message(&incoming.hook_key, index);
+                reword::build_context(&body, format, message, &headers, &incoming.params)?
+            }
+            false => tera::Context::new(),
+        };
+        // parameters are filled in after rendering, so they are never run as a template
+        let href = fill_params(
+            &self.templates.render(&name("href"), &hook.href, &context)?,
+            &incoming.params,
+        );
+        if let Some(reword) = &hook.reword {
+            // reword the body
+            body = Body {
+                content: self
+                    .templates
+                    .render(&name("reword.content"), &reword.content, &context)?
+                    .into(),
+                content_type,
+            };
+            // reword headers
+            for (key, value) in &reword.set_headers {
+                let field = format!("reword.set_headers.{}", key);
+                let value = self.templates.render(&name(&field), value, &context)?;
+                // a rendered value can come from the body, so may not be a valid header
+                if HeaderName::from_bytes(key.as_bytes()).is_err()
+                    || HeaderValue::from_str(&value).is_err()
+                {
+                    return Err(RewordErrors::InvalidHeader(key.clone()));
                 }
+                headers.insert(key.clone(), value);
+            }
+        }
+        Ok(ToDispatch {
+            href,
+            body,
+            headers,
+            hook: incoming.hook_key.clone(),
+            target: Some(Target {
+                index,
+                href: hook.href.clone(),
+                sign: hook.sign,
+            }),
+        })
+    }
+
+    /// Make the first attempt at sending to a target
+    async fn attempt_hook(
+        &self,
+        hook: &HookOut,
+        index: usize,
+        incoming: &Incoming,
+    ) -> Result<(), FailedAttempt> {
+        let to_dispatch = match self.prepare(hook, index, incoming) {
+            Ok(to_dispatch) => to_dispatch,
+            Err(err) => {
+                log::error!("failed to reword webhook for {}: {}", hook.href, err);
+                return Err(None);
             }
         };
         // send the actual request
-        self.dispatch(to_dispatch).await;
+        self.dispatch(&to_dispatch, &hook.accept_status)
+            .await
+            .map_err(|err| Some((Delivery::new(to_dispatch, hook), err)))
+    }
+
+    /// Queue failed first attempts for retry, as they complete
+    async fn queue_failed<F>(self: Arc<Self>, mut attempts: FuturesUnordered<F>)
+    where
+        F: Future<Output = Result<(), FailedAttempt>>,
+    {
+        while let Some(result) = attempts.next().await {
+            if let Err(Some((delivery, err))) = result {
+                self.failed_attempt(delivery, err);
+            }
+        }
+    }
+
+    /// Whether a target's condition is met, so it should be sent to
+    fn condition_met(&self, hook: &HookOut, index: usize, incoming: &Incoming) -> bool {
+        let when = match &hook.when {
+            Some(when) => when,
+            None => return true,
+        };
+        let name = template_name(&incoming.hook_key, index, "when");
+        let format = hook.reword.as_ref().and_then(|reword| reword.decode);
+        let message = self.decoders.message(&incoming.hook_key, index);
+        let result = condition::build_context(incoming, when, format, message)
+            .and_then(|context| self.templates.evaluate(&name, &context));
+        match result {
+            Ok(true) => true,
+            Ok(false) => {
+                log::info!("skipped webhook to {} as condition not met", hook.href);
+                false
+            }
+            Err(err) => {
+                log::error!(
+                    "skipped webhook to {} as condition failed: {}",
+                    hook.href,
+                    err
+                );
+                false
+            }
+        }
+    }
+
+    /// Settle the first attempts when the sender is waiting on them,
+    /// leaving the retry to the sender only when every target can be sent again,
+    /// otherwise failures are retried or recorded here so no target is sent to twice
+    fn settle(&self, results: Vec<Result<(), FailedAttempt>>) -> Acknowledgement {
+        let retryable = |result: &Result<(), FailedAttempt>| match result {
+            Ok(()) => false,
+            Err(Some((_, err))) => err.is_retryable(),
+            Err(None) => false,
+        };
+        let succeeded = results.iter().any(Result::is_ok);
+        if !succeeded && results.iter().all(retryable) {
+            log::warn!("webhook not received by any target, leaving retry to sender");
+            return Acknowledgement::Failed;
+        }
+        let acknowledgement = match succeeded || results.iter().any(retryable) {
+            true => Acknowledgement::Received,
+            false => Acknowledgement::Rejected,
+        };
+        for (delivery, err) in results.into_iter().filter_map(Result::err).flatten() {
+            self.failed_attempt(delivery, err);
+        }
+        acknowledgement
     }
 
+    /// Send a webhook to all targets,
+    /// returning how it was acknowledged for the hook's `ack` mode
     pub async fn dispatch_hooks(
-        &self,
-        hooks: &[HookOut],
-        body: Body,
-        headers: HashMap<String, String>,
-    ) {
-        // TODO switch to std::futures when it's out of experimental
-        let to_dispatch = hooks.iter().map(|hook| {
-            let mut hook_headers = HashMap::new();
-            for key in &hook.keep_headers {
-                if let Some(value) = headers.get(&key.to_lowercase()) {
-                    hook_headers.insert(key.clone(), value.clone());
+        self: Arc<Self>,
+        incoming: Incoming,
+        hooks: Vec<HookOut>,
+        ack: HookAck,
+    ) -> Acknowledgement {
+        // skip targets with a condition that is not met
+        let hooks: Vec<(usize, HookOut)> = hooks
+            .into_iter()
+            .enumerate()
+            .filter(|(index, hook)| self.condition_met(hook, *index, &incoming))
+            .collect();
+        if hooks.is_empty() {
+            return Acknowledgement::Received;
+        }
+        let incoming = Arc::new(incoming);
+        let mut attempts: FuturesUnordered<_> = hooks
+            .into_iter()
+            .map(|(index, hook)| {
+                let dispatcher = self.clone();
+                let incoming = incoming.clone();
+                async move { dispatcher.attempt_hook(&hook, index, &incoming).await }
+            })
+            .collect();
+        match ack {
+            HookAck::Immediate => {
+                tokio::spawn(self.queue_failed(attempts));
+                Acknowledgement::Received
+            }
+            HookAck::All => {
+                let results: Vec<_> = attempts.collect().await;
+                self.settle(results)
+            }
+            HookAck::Any => {
+                let mut failed = Vec::new();
+                while let Some(result) = attempts.next().await {
+                    match result {
+                        Ok(()) => {
+                            for (delivery, err) in failed.into_iter().flatten() {
+                                self.failed_attempt(delivery, err);
+                            }
+                            tokio::spawn(self.queue_failed(attempts));
+                            return Acknowledgement::Received;
+                        }
+                        Err(err) => failed.push(err),
+                    }
                 }
+                self.settle(failed.into_iter().map(Err).collect())
             }
-            self.dispatch_hook(hook, body.clone(), hook_headers)
-        });
-        join_all(to_dispatch).await;
+        }
     }
 }
 
 impl Default for Dispatcher {
     fn default() -> Self {
-        Self::new()
+        Self::new(
+            DeliveryQueue::in_memory(),
+            None,
+            Templates::default(),
+            Decoders::default(),
+            Signers::default(),
+            Arc::new(Metrics::new()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mighty_hooks_config::Config;
+    use mighty_hooks_core::signing;
+    use reqwest::StatusCode;
+
+    use super::*;
+
+    fn config(href: &str) -> Config {
+        serde_yaml::from_str(&format!(
+            r#"
+host: 0.0.0.0
+port: 8000
+hooks:
+  "{{name}}.example.com/hello":
+    in:
+      content_type: application/json
+    out:
+      - href: "{}"
+"#,
+            href
+        ))
+        .unwrap()
+    }
+
+    /// Create a dispatcher for a config, with a webhook received by its first hook
+    fn receive(config: &Config, name: &str, body: &str) -> (Dispatcher, Incoming) {
+        let dispatcher = Dispatcher::new(
+            DeliveryQueue::in_memory(),
+            None,
+            Templates::from_config(config).unwrap(),
+            Decoders::from_config(config).unwrap(),
+            Signers::from_config(config),
+            Arc::new(Metrics::new()),
+        );
+        let incoming = Incoming {
+            hook_key: config.hooks.keys().next().unwrap().clone(),
+            params: HashMap::from([(String::from("name"), name.to_owned())]),
+            body: Body {
+                content: body.to_owned().into(),
+                content_type: String::from("application/json"),
+            },
+            headers: HashMap::new(),
+        };
+        (dispatcher, incoming)
+    }
+
+    fn prepare(href: &str, name: &str) -> ToDispatch {
+        let config = config(href);
+        let (dispatcher, incoming) = receive(&config, name, "{}");
+        let hook = &config.hooks[&incoming.hook_key].out[0];
+        dispatcher.prepare(hook, 0, &incoming).unwrap()
+    }
+
+    fn reworded_config(extra: &str) -> Config {
+        serde_yaml::from_str(&format!(
+            r#"
+host: 0.0.0.0
+port: 8000
+hooks:
+  hooks.example.com/hello:
+    in:
+      content_type: application/json
+    out:
+      - href: http://example.com/hello
+        reword:
+          content: "{{{{ content.json.name }}}}"
+          set_headers:
+            x-name: "{{{{ content.json.name }}}}"
+{}
+"#,
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_prepare_href() {
+        let to_dispatch = prepare("http://{name}.example.com/{{ params.name }}", "repo");
+        assert_eq!("http://repo.example.com/repo", to_dispatch.href);
+        let to_dispatch = prepare("http://example.com/{name}", "repo");
+        assert_eq!("http://example.com/repo", to_dispatch.href);
+    }
+
+    #[test]
+    fn test_prepare_href_param_not_rendered() {
+        // a parameter comes from the request path, so must never be run as a template
+        let param = "{{ get_env(name=\"HOME\") }}";
+        let to_dispatch = prepare("http://example.com/{name}/{{ params.name }}", param);
+        assert_eq!(format!("http://example.com/{}/{}", param, param), to_dispatch.href);
+    }
+
+    #[test]
+    fn test_prepare_invalid_header() {
+        let config = reworded_config("");
+        let hook = &config.hooks["hooks.example.com/hello"].out[0];
+        let (dispatcher, incoming) = receive(&config, "repo", r#"{"name": "repo"}"#);
+        let to_dispatch = dispatcher.prepare(hook, 0, &incoming).unwrap();
+        assert_eq!("repo", to_dispatch.headers["x-name"]);
+        // a value from the body cannot be used to break a header
+        let (dispatcher, incoming) = receive(&config, "repo", r#"{"name": "a\nb: c"}"#);
+        let result = dispatcher.prepare(hook, 0, &incoming);
+        assert!(matches!(result, Err(RewordErrors::InvalidHeader(key)) if key == "x-name"));
+        let headers = HashMap::from([(String::from("x-name"), String::from("a\nb"))]);
+        assert!(headers_convert(&headers).is_err());
+    }
+
+    #[test]
+    fn test_sign_reworded() {
+        let secret = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw";
+        for sign in ["github", "standard-webhooks"] {
+            let extra = format!("        secret_256: {}\n        sign: {}", secret, sign);
+            let config = reworded_config(&extra);
+            let hook = &config.hooks["hooks.example.com/hello"].out[0];
+            let (dispatcher, incoming) = receive(&config, "repo", r#"{"name": "repo"}"#);
+            let to_dispatch = dispatcher.prepare(hook, 0, &incoming).unwrap();
+            let mut headers = to_dispatch.headers.clone();
+            let target = to_dispatch.target.as_ref().unwrap();
+            dispatcher
+                .signers
+                .sign(&to_dispatch.hook, target, &to_dispatch.body, &mut headers)
+                .unwrap();
+            // the reworded body is what is sent, so what is signed
+            let content = &to_dispatch.body.content;
+            assert_eq!("repo", content);
+            let verified = match hook.sign {
+                SignScheme::Github => {
+                    let signature = &headers["X-Hub-Signature-256"]["sha256=".len()..];
+                    signing::verify_hmac_sha256(secret, content, signature)
+                }
+                SignScheme::StandardWebhooks => signing::verify_standard_webhook(
+                    secret,
+                    &headers[signer::WEBHOOK_ID],
+                    &headers["webhook-timestamp"],
+                    content,
+                    &headers["webhook-signature"],
+                ),
+            };
+            assert!(verified, "{}", sign);
+        }
+    }
+
+    #[test]
+    fn test_settle() {
+        let config = config("http://example.com");
+        let hook = &config.hooks.values().next().unwrap().out[0];
+        let failed = |err| Some((Delivery::new(prepare(&hook.href, "repo"), hook), err));
+        let retryable = || failed(DeliveryError::ServerError(StatusCode::BAD_GATEWAY));
+        let rejected = || failed(DeliveryError::ClientError(StatusCode::BAD_REQUEST));
+        let dispatcher = Dispatcher::default();
+        // the sender can send to every target again
+        let results = vec![Err(retryable()), Err(retryable())];
+        assert_eq!(Acknowledgement::Failed, dispatcher.settle(results));
+        assert_eq!(None, dispatcher.queue.next_due());
+        assert_eq!(Acknowledgement::Rejected, dispatcher.settle(vec![Err(rejected()), Err(None)]));
+        // a target received it, so the failure is queued instead of sent again by the sender
+        assert_eq!(Acknowledgement::Received, dispatcher.settle(vec![Ok(()), Err(retryable())]));
+        assert!(dispatcher.queue.next_due().is_some());
+    }
+
+    #[test]
+    fn test_retry_after_capped() {
+        let config = config("http://example.com");
+        let hook = &config.hooks.values().next().unwrap().out[0];
+        let delivery = Delivery::new(prepare(&hook.href, "repo"), hook);
+        let max_delay_ms = delivery.retry.max_delay_ms;
+        let dispatcher = Dispatcher::default();
+        let retry_after = Duration::from_secs(365 * 24 * 60 * 60);
+        dispatcher.failed_attempt(delivery, DeliveryError::RateLimited(Some(retry_after)));
+        assert!(dispatcher.queue.next_due().unwrap() <= queue::now_ms() + max_delay_ms);
     }
 }
@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use mighty_hooks_config::{HookOut, RetryPolicy};
+use mighty_hooks_core::Body;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::{
+    signer::Target,
+    store::{JsonDir, JsonDirWriter},
+    ToDispatch,
+};
+
+/// Get the current time as milliseconds since the unix epoch
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Serialize raw bytes as a base64 string
+mod base64_bytes {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        STANDARD.decode(value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A prepared webhook that has failed to send and is waiting to be retried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: String,
+    /// Key of the hook that received the webhook
+    #[serde(default)]
+    pub hook: String,
+    pub href: String,
+    pub content_type: String,
+    #[serde(with = "base64_bytes")]
+    pub body: Vec<u8>,
+    pub headers: HashMap<String, String>,
+    /// Target in the hook, used to sign each attempt
+    #[serde(default)]
+    pub target: Option<Target>,
+    pub retry: RetryPolicy,
+    /// Response statuses counted as delivered, any 2xx if empty
+    #[serde(default)]
+    pub accept_status: Vec<u16>,
+    /// Number of attempts made so far
+    pub attempts: u32,
+    /// When the next attempt is due, in milliseconds since the unix epoch
+    pub next_attempt_at: u64,
+    /// Why the last attempt failed
+    pub last_error: Option<String>,
+}
+
+impl Delivery {
+    pub(crate) fn new(to_dispatch: ToDispatch, hook: &HookOut) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            hook: to_dispatch.hook,
+            href: to_dispatch.href,
+            content_type: to_dispatch.body.content_type,
+            body: to_dispatch.body.content.to_vec(),
+            headers: to_dispatch.headers,
+            target: to_dispatch.target,
+            retry: hook.retry.clone(),
+            accept_status: hook.accept_status.clone(),
+            attempts: 0,
+            next_attempt_at: now_ms(),
+            last_error: None,
+        }
+    }
+
+    pub(crate) fn to_dispatch(&self) -> ToDispatch {
+        ToDispatch {
+            href: self.href.clone(),
+            body: Body {
+                content: self.body.clone().into(),
+                content_type: self.content_type.clone(),
+            },
+            headers: self.headers.clone(),
+            hook: self.hook.clone(),
+            target: self.target.clone(),
+        }
+    }
+}
+
+/// Deliveries waiting to be retried,
+/// optionally persisted to a directory so they survive restarts
+pub struct DeliveryQueue {
+    writer: Option<JsonDirWriter>,
+    pending: Mutex<HashMap<String, Delivery>>,
+    changed: Notify,
+}
+
+impl DeliveryQueue {
+    /// Create a queue that is only held in memory
+    pub fn in_memory() -> Self {
+        Self {
+            writer: None,
+            pending: Mutex::new(HashMap::new()),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Open a queue persisted in a directory,
+    /// loading any deliveries left over from a previous run
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = JsonDir::open(dir)?;
+        let pending: HashMap<String, Delivery> = dir
+            .read_all::<Delivery>()?
+            .into_iter()
+            .map(|delivery| (delivery.id.clone(), delivery))
+            .collect();
+        if !pending.is_empty() {
+            log::info!("loaded {} queued deliveries for retry", pending.len());
+        }
+        Ok(Self {
+            writer: Some(JsonDirWriter::spawn(dir, "delivery-queue")?),
+            pending: Mutex::new(pending),
+            changed: Notify::new(),
+        })
+    }
+
+    /// Add or update a delivery in the queue
+    pub fn push(&self, delivery: Delivery) {
+        // persisted while locked, so changes are persisted in the order they were made
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(writer) = &self.writer {
+            writer.write(&delivery.id, &delivery);
+        }
+        pending.insert(delivery.id.clone(), delivery);
+        drop(pending);
+        self.changed.notify_one();
+    }
+
+    /// Remove a delivery from the queue
+    pub fn remove(&self, id: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.remove(id);
+        if let Some(writer) = &self.writer {
+            writer.remove(id);
+        }
+    }
+
+    /// Get all deliveries due for another attempt
+    pub fn due(&self, now: u64) -> Vec<Delivery> {
+        self.pending
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|delivery| delivery.next_attempt_at <= now)
+            .cloned()
+            .collect()
+    }
+
+    /// Get when the next delivery is due
+    pub fn next_due(&self) -> Option<u64> {
+        self.pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|delivery| delivery.next_attempt_at)
+            .min()
+    }
+
+    /// Wait until the queue is changed
+    pub async fn changed(&self) {
+        self.changed.notified().await
+    }
+}
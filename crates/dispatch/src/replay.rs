@@ -0,0 +1,266 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    path::Path,
+    sync::Mutex,
+};
+
+use mighty_hooks_config::ReplayGuard;
+use mighty_hooks_core::signing::sha256_hex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    queue::now_ms,
+    store::{JsonDir, JsonDirWriter},
+};
+
+/// A delivery received by a hook with a replay guard
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Seen {
+    hook: String,
+    key: String,
+    /// When to forget the delivery, in milliseconds since the unix epoch
+    expires_at: u64,
+}
+
+impl Seen {
+    /// Id of the file the delivery is persisted in
+    fn file_id(hook: &str, key: &str) -> String {
+        sha256_hex(format!("{}\n{}", hook, key).as_bytes())
+    }
+}
+
+/// Deliveries seen by one hook, ordered by when they expire
+#[derive(Debug, Default)]
+struct SeenSet {
+    expires: HashMap<String, u64>,
+    order: BTreeSet<(u64, String)>,
+}
+
+impl SeenSet {
+    fn insert(&mut self, key: String, expires_at: u64) {
+        if let Some(previous) = self.expires.insert(key.clone(), expires_at) {
+            self.order.remove(&(previous, key.clone()));
+        }
+        self.order.insert((expires_at, key));
+    }
+
+    fn remove(&mut self, key: &str) -> bool {
+        match self.expires.remove(key) {
+            Some(expires_at) => self.order.remove(&(expires_at, key.to_owned())),
+            None => false,
+        }
+    }
+
+    /// Forget deliveries that have expired, or are the oldest above `max_entries`,
+    /// returning their keys
+    fn evict(&mut self, now: u64, max_entries: usize) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while let Some((expires_at, _)) = self.order.first() {
+            if *expires_at > now && self.order.len() <= max_entries {
+                break;
+            }
+            let (_, key) = self.order.pop_first().unwrap();
+            self.expires.remove(&key);
+            evicted.push(key);
+        }
+        evicted
+    }
+}
+
+/// Remembers deliveries received by hooks with a replay guard,
+/// optionally persisted to a directory so they survive restarts,
+/// the files are written by a background thread so requests never wait on them
+pub struct ReplayCache {
+    writer: Option<JsonDirWriter>,
+    hooks: Mutex<HashMap<String, SeenSet>>,
+}
+
+impl ReplayCache {
+    /// Create a cache that is only held in memory
+    pub fn in_memory() -> Self {
+        Self {
+            writer: None,
+            hooks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Open a cache persisted in a directory,
+    /// loading deliveries seen in a previous run that have not expired
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = JsonDir::open(dir)?;
+        let now = now_ms();
+        let mut hooks: HashMap<String, SeenSet> = HashMap::new();
+        for seen in dir.read_all::<Seen>()? {
+            match seen.expires_at > now {
+                true => hooks
+                    .entry(seen.hook)
+                    .or_default()
+                    .insert(seen.key, seen.expires_at),
+                false => {
+                    dir.remove(&Seen::file_id(&seen.hook, &seen.key))?;
+                }
+            }
+        }
+        Ok(Self {
+            writer: Some(JsonDirWriter::spawn(dir, "replay-cache")?),
+            hooks: Mutex::new(hooks),
+        })
+    }
+
+    /// Get the key identifying a delivery,
+    /// or None when the guard's header is missing
+    pub fn delivery_key(
+        guard: &ReplayGuard,
+        headers: &HashMap<String, String>,
+        body: &[u8],
+    ) -> Option<String> {
+        match &guard.header {
+            Some(header) => headers
+                .get(&header.to_lowercase())
+                .filter(|value| !value.is_empty())
+                .cloned(),
+            None => Some(sha256_hex(body)),
+        }
+    }
+
+    /// Record a delivery as seen, returning false if it already was,
+    /// checked and recorded under one lock so only one of many duplicates is let through
+    pub fn first_seen(&self, hook: &str, key: &str, guard: &ReplayGuard) -> bool {
+        let now = now_ms();
+        let mut hooks = self.hooks.lock().unwrap();
+        let seen = hooks.entry(hook.to_owned()).or_default();
+        let mut forgotten = seen.evict(now, guard.max_entries);
+        if seen.expires.contains_key(key) {
+            self.remove_files(hook, forgotten);
+            return false;
+        }
+        // saturated, so a very long ttl is kept until evicted instead of overflowing
+        let expires_at = now.saturating_add(guard.ttl_secs.saturating_mul(1000));
+        seen.insert(key.to_owned(), expires_at);
+        forgotten.extend(seen.evict(now, guard.max_entries));
+        self.remove_files(hook, forgotten);
+        if let Some(writer) = &self.writer {
+            let entry = Seen {
+                hook: hook.to_owned(),
+                key: key.to_owned(),
+                expires_at,
+            };
+            writer.write(&Seen::file_id(hook, key), &entry);
+        }
+        true
+    }
+
+    /// Forget a delivery, so it is accepted if sent again
+    pub fn forget(&self, hook: &str, key: &str) {
+        // hold the lock while persisting, so changes are persisted in the order they were made
+        let mut hooks = self.hooks.lock().unwrap();
+        if hooks.get_mut(hook).is_some_and(|seen| seen.remove(key)) {
+            self.remove_files(hook, vec![key.to_owned()]);
+        }
+    }
+
+    fn remove_files(&self, hook: &str, keys: Vec<String>) {
+        if let Some(writer) = &self.writer {
+            for key in keys {
+                writer.remove(&Seen::file_id(hook, &key));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard(max_entries: usize) -> ReplayGuard {
+        ReplayGuard {
+            header: Some(String::from("X-GitHub-Delivery")),
+            ttl_secs: 60,
+            max_entries,
+        }
+    }
+
+    #[test]
+    fn test_first_seen() {
+        let cache = ReplayCache::in_memory();
+        let guard = guard(2);
+        assert!(cache.first_seen("hook", "a", &guard));
+        assert!(!cache.first_seen("hook", "a", &guard));
+        assert!(cache.first_seen("other", "a", &guard));
+        cache.forget("hook", "a");
+        assert!(cache.first_seen("hook", "a", &guard));
+        let guard = ReplayGuard {
+            ttl_secs: u64::MAX,
+            ..guard
+        };
+        assert!(cache.first_seen("hook", "b", &guard));
+        assert!(!cache.first_seen("hook", "b", &guard));
+    }
+
+    #[test]
+    fn test_first_seen_concurrent() {
+        let cache = ReplayCache::in_memory();
+        let guard = guard(10);
+        let passed = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| cache.first_seen("hook", "a", &guard)))
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .filter(|passed| *passed)
+                .count()
+        });
+        assert_eq!(1, passed);
+    }
+
+    #[test]
+    fn test_persisted() {
+        let dir = std::env::temp_dir().join(format!("mighty-hooks-replay-{}", std::process::id()));
+        let guard = guard(10);
+        let cache = ReplayCache::open(&dir).unwrap();
+        assert!(cache.first_seen("hook", "a", &guard));
+        assert!(cache.first_seen("hook", "b", &guard));
+        cache.forget("hook", "b");
+        drop(cache);
+        let cache = ReplayCache::open(&dir).unwrap();
+        assert!(!cache.first_seen("hook", "a", &guard));
+        assert!(cache.first_seen("hook", "b", &guard));
+        drop(cache);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let mut seen = SeenSet::default();
+        seen.insert(String::from("a"), 10);
+        seen.insert(String::from("b"), 20);
+        seen.insert(String::from("c"), 30);
+        assert_eq!(vec!["a"], seen.evict(0, 2));
+        assert_eq!(vec!["b"], seen.evict(25, 2));
+        assert!(seen.expires.contains_key("c"));
+    }
+
+    #[test]
+    fn test_delivery_key() {
+        let guard = guard(1);
+        let headers = HashMap::from([(String::from("x-github-delivery"), String::from("1"))]);
+        assert_eq!(
+            Some(String::from("1")),
+            ReplayCache::delivery_key(&guard, &headers, b"")
+        );
+        assert_eq!(
+            None,
+            ReplayCache::delivery_key(&guard, &HashMap::new(), b"")
+        );
+        let guard = ReplayGuard {
+            header: None,
+            ..guard
+        };
+        assert_eq!(
+            Some(sha256_hex(b"body")),
+            ReplayCache::delivery_key(&guard, &HashMap::new(), b"body")
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use mighty_hooks_config::RetryPolicy;
+use rand::Rng;
+
+/// Get how long to wait before the next attempt,
+/// given how many attempts have already been made
+pub fn backoff_delay(policy: &RetryPolicy, attempts: u32) -> Duration {
+    let exponent = attempts.saturating_sub(1);
+    let delay = policy
+        .base_delay_ms
+        .saturating_mul(2u64.saturating_pow(exponent));
+    let jitter = match policy.jitter_ms {
+        0 => 0,
+        jitter_ms => rand::thread_rng().gen_range(0..=jitter_ms),
+    };
+    Duration::from_millis(delay.saturating_add(jitter).min(policy.max_delay_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter_ms: u64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 10,
+            base_delay_ms: 1000,
+            jitter_ms,
+            max_delay_ms: 10_000,
+        }
+    }
+
+    #[test]
+    fn test_backoff_delay_doubles() {
+        let policy = policy(0);
+        assert_eq!(Duration::from_millis(1000), backoff_delay(&policy, 1));
+        assert_eq!(Duration::from_millis(2000), backoff_delay(&policy, 2));
+        assert_eq!(Duration::from_millis(8000), backoff_delay(&policy, 4));
+    }
+
+    #[test]
+    fn test_backoff_delay_capped() {
+        let policy = policy(0);
+        assert_eq!(Duration::from_millis(10_000), backoff_delay(&policy, 5));
+        assert_eq!(Duration::from_millis(10_000), backoff_delay(&policy, 100));
+    }
+
+    #[test]
+    fn test_backoff_delay_jitter() {
+        let policy = policy(500);
+        for _ in 0..50 {
+            let delay = backoff_delay(&policy, 2);
+            assert!(delay >= Duration::from_millis(2000));
+            assert!(delay <= Duration::from_millis(2500));
+        }
+    }
+}
@@ -1,44 +1,85 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
-use mighty_hooks_config::HookReword;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mighty_hooks_config::BodyFormat;
 use mighty_hooks_core::Body;
+use prost_reflect::MessageDescriptor;
 use serde::Serialize;
 
+use crate::decode::decode_body;
+
 #[derive(Debug)]
 pub enum RewordErrors {
-    BodyMustBeText,
-    DeserializeBodyError,
-    TemplateError,
+    DeserializeBodyError(String),
+    TemplateError(String),
+    /// A set header rendered to a value that cannot be sent
+    InvalidHeader(String),
+}
+
+impl fmt::Display for RewordErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DeserializeBodyError(message) => write!(f, "{}", message),
+            Self::TemplateError(message) => {
+                write!(f, "template could not be rendered: {}", message)
+            }
+            Self::InvalidHeader(key) => write!(f, "invalid value for header \"{}\"", key),
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct ContentContext<'a> {
     pub headers: &'a HashMap<String, String>,
-    pub raw: String,
+    /// Not set when the body is not text
+    pub raw: Option<String>,
+    /// Only set when the body is not text
+    pub base64: Option<String>,
     pub json: Option<serde_json::Value>,
+    pub form: Option<serde_json::Map<String, serde_json::Value>>,
+    pub data: Option<serde_json::Value>,
 }
 
-fn deserialize_json(content: &[u8]) -> Result<serde_json::Value, RewordErrors> {
-    serde_json::from_slice(content).map_err(|_| RewordErrors::DeserializeBodyError)
+/// Get a message from a tera error, including the errors that caused it
+pub fn tera_error_message(err: &tera::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = std::error::Error::source(err);
+    while let Some(err) = source {
+        message.push_str(&format!(": {}", err));
+        source = err.source();
+    }
+    message
+}
+
+/// Whether a value contains tera syntax, so needs rendering
+pub fn is_template(value: &str) -> bool {
+    value.contains("{{") || value.contains("{%") || value.contains("{#")
 }
 
-pub fn reword_body(
-    reword: &HookReword,
+/// Build the context templates are rendered with,
+/// decoding the body as `format`, the Protobuf `message`, or by its content type
+pub fn build_context(
     body: &Body,
+    format: Option<BodyFormat>,
+    message: Option<&MessageDescriptor>,
     headers: &HashMap<String, String>,
-) -> Result<String, RewordErrors> {
+    params: &HashMap<String, String>,
+) -> Result<tera::Context, RewordErrors> {
     let mut tera_context = tera::Context::new();
+    tera_context.insert("params", params);
     // add content to the context for access in template
-    let mut content_context = ContentContext {
+    let decoded = decode_body(body, format, message)
+        .map_err(|err| RewordErrors::DeserializeBodyError(err.to_string()))?;
+    let raw = String::from_utf8(body.content.to_vec()).ok();
+    let base64 = raw.is_none().then(|| STANDARD.encode(&body.content));
+    let content_context = ContentContext {
         headers,
-        raw: String::from_utf8(body.content.to_vec()).map_err(|_| RewordErrors::BodyMustBeText)?,
-        json: None,
+        raw,
+        base64,
+        json: decoded.json,
+        form: decoded.form,
+        data: decoded.data,
     };
-    if body.content_type == "application/json" {
-        content_context.json = Some(deserialize_json(&body.content)?);
-    }
     tera_context.insert("content", &content_context);
-    // render the template
-    tera::Tera::one_off(&reword.content, &tera_context, false)
-        .map_err(|_| RewordErrors::TemplateError)
+    Ok(tera_context)
 }
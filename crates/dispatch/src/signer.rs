@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use mighty_hooks_config::{Config, SignScheme};
+use mighty_hooks_core::{signing, Body};
+use serde::{Deserialize, Serialize};
+
+use crate::{failure::DeliveryError, queue::now_ms};
+
+/// Header holding the id of a Standard Webhooks message, kept between attempts
+pub(crate) const WEBHOOK_ID: &str = "webhook-id";
+
+/// Create the id of a Standard Webhooks message
+pub(crate) fn new_webhook_id() -> String {
+    format!("msg_{}", uuid::Uuid::new_v4().simple())
+}
+
+/// The target in a hook a delivery is for,
+/// only signed while the target in the config is still the same
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Target {
+    /// Index of the target in the hook
+    pub index: usize,
+    /// Href of the target as configured, before rendering
+    pub href: String,
+    pub sign: SignScheme,
+}
+
+#[derive(Debug)]
+struct Signer {
+    target: Target,
+    secret: Option<String>,
+}
+
+/// Secrets of all targets, by hook key and target index,
+/// requests are signed for every attempt as a signature can include when it was sent
+#[derive(Debug, Default)]
+pub struct Signers {
+    signers: HashMap<(String, usize), Signer>,
+}
+
+impl Signers {
+    pub fn from_config(config: &Config) -> Self {
+        let mut signers = HashMap::new();
+        for (hook_key, hook) in &config.hooks {
+            for (index, out) in hook.out.iter().enumerate() {
+                let target = Target {
+                    index,
+                    href: out.href.clone(),
+                    sign: out.sign,
+                };
+                let secret = out.secret_256.clone();
+                signers.insert((hook_key.clone(), index), Signer { target, secret });
+            }
+        }
+        Self { signers }
+    }
+
+    /// Sign the body being sent to a target,
+    /// refusing when the target has since been changed or removed from the config
+    pub(crate) fn sign(
+        &self,
+        hook_key: &str,
+        target: &Target,
+        body: &Body,
+        headers: &mut HashMap<String, String>,
+    ) -> Result<(), DeliveryError> {
+        let signer = self
+            .signers
+            .get(&(hook_key.to_owned(), target.index))
+            .filter(|signer| signer.target == *target)
+            .ok_or(DeliveryError::TargetChanged)?;
+        let secret = match &signer.secret {
+            Some(secret) => secret,
+            None => return Ok(()),
+        };
+        match target.sign {
+            SignScheme::Github => {
+                let signature = signing::sign_hmac_sha256(secret, &body.content);
+                headers.insert(
+                    "X-Hub-Signature-256".to_string(),
+                    format!("sha256={}", signature),
+                );
+            }
+            SignScheme::StandardWebhooks => {
+                let id = headers
+                    .entry(WEBHOOK_ID.to_string())
+                    .or_insert_with(new_webhook_id)
+                    .clone();
+                let timestamp = now_ms() / 1000;
+                let signature =
+                    signing::sign_standard_webhook(secret, &id, timestamp, &body.content)
+                        .ok_or_else(|| {
+                            DeliveryError::Request(String::from("invalid signing secret"))
+                        })?;
+                headers.insert("webhook-timestamp".to_string(), timestamp.to_string());
+                headers.insert("webhook-signature".to_string(), signature);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signers(sign: SignScheme, secret: &str) -> (Signers, Target) {
+        let target = Target {
+            index: 0,
+            href: String::from("http://example.com/hello"),
+            sign,
+        };
+        let signer = Signer {
+            target: target.clone(),
+            secret: Some(secret.to_owned()),
+        };
+        let signers = Signers {
+            signers: HashMap::from([((String::from("hook"), 0), signer)]),
+        };
+        (signers, target)
+    }
+
+    fn body() -> Body {
+        Body {
+            content: "{}".into(),
+            content_type: String::from("application/json"),
+        }
+    }
+
+    #[test]
+    fn test_sign_standard_webhooks() {
+        let secret = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw";
+        let (signers, target) = signers(SignScheme::StandardWebhooks, secret);
+        let mut headers = HashMap::from([(WEBHOOK_ID.to_string(), String::from("msg_1"))]);
+        signers.sign("hook", &target, &body(), &mut headers).unwrap();
+        assert_eq!("msg_1", headers[WEBHOOK_ID]);
+        assert!(signing::verify_standard_webhook(
+            secret,
+            "msg_1",
+            &headers["webhook-timestamp"],
+            &body().content,
+            &headers["webhook-signature"]
+        ));
+    }
+
+    #[test]
+    fn test_sign_target_changed() {
+        let (signers, target) = signers(SignScheme::Github, "It's a Secret to Everybody");
+        let mut headers = HashMap::new();
+        signers.sign("hook", &target, &body(), &mut headers).unwrap();
+        assert!(headers.contains_key("X-Hub-Signature-256"));
+        // never signed with the secret of another target
+        let moved = Target {
+            href: String::from("http://example.com/other"),
+            ..target.clone()
+        };
+        let result = signers.sign("hook", &moved, &body(), &mut HashMap::new());
+        assert_eq!(Err(DeliveryError::TargetChanged), result);
+        let removed = Target { index: 1, ..target };
+        let result = signers.sign("hook", &removed, &body(), &mut HashMap::new());
+        assert_eq!(Err(DeliveryError::TargetChanged), result);
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A directory storing each item as its own JSON file
+#[derive(Debug, Clone)]
+pub struct JsonDir {
+    dir: PathBuf,
+}
+
+impl JsonDir {
+    /// Open a directory, creating it if needed
+    /// and removing files left half written by a crash
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.to_string_lossy().ends_with(".json.tmp") {
+                log::warn!("removing half written file {}", path.display());
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(Self {
+            dir: dir.to_owned(),
+        })
+    }
+
+    /// Get the path of an item, only allowing ids in the form they are generated,
+    /// so an id given by a user can never reach outside the directory
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        let valid = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        match valid {
+            true => Ok(self.dir.join(format!("{}.json", id))),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid id \"{}\"", id),
+            )),
+        }
+    }
+
+    /// Read all items, skipping any that cannot be read
+    pub fn read_all<T: DeserializeOwned>(&self) -> io::Result<Vec<T>> {
+        let mut items = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match fs::read(&path).map(|content| serde_json::from_slice::<T>(&content)) {
+                Ok(Ok(item)) => items.push(item),
+                _ => log::warn!("skipping unreadable file {}", path.display()),
+            }
+        }
+        Ok(items)
+    }
+
+    /// Read a single item
+    pub fn read<T: DeserializeOwned>(&self, id: &str) -> io::Result<T> {
+        Ok(serde_json::from_slice(&fs::read(self.path(id)?)?)?)
+    }
+
+    /// Write a serialized item, replacing any with the same id
+    fn write(&self, id: &str, content: &[u8]) -> io::Result<()> {
+        // write then rename, so a crash never leaves a half written file
+        let path = self.path(id)?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, content)?;
+        fs::rename(tmp_path, path)
+    }
+
+    /// Remove an item, returning whether it existed
+    pub fn remove(&self, id: &str) -> io::Result<bool> {
+        match fs::remove_file(self.path(id)?) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// A change to make to a directory
+enum Change {
+    Write(String, Vec<u8>),
+    Remove(String),
+}
+
+/// Makes changes to a directory on a background thread, in the order they were made,
+/// so async tasks never wait on the disk
+pub struct JsonDirWriter {
+    sender: Option<mpsc::Sender<Change>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl JsonDirWriter {
+    /// Start writing to a directory
+    pub fn spawn(dir: JsonDir, name: &str) -> io::Result<Self> {
+        let (sender, changes) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || {
+                for change in changes {
+                    let (id, result) = match change {
+                        Change::Write(id, content) => {
+                            let result = dir.write(&id, &content);
+                            (id, result)
+                        }
+                        Change::Remove(id) => {
+                            let result = dir.remove(&id).map(|_| ());
+                            (id, result)
+                        }
+                    };
+                    if let Err(err) = result {
+                        log::error!("failed to persist {} in {}: {}", id, dir.dir.display(), err);
+                    }
+                }
+            })?;
+        Ok(Self {
+            sender: Some(sender),
+            handle: Some(handle),
+        })
+    }
+
+    fn send(&self, change: Change) {
+        if let Some(sender) = &self.sender {
+            // only fails if the thread panicked, which is already reported
+            let _ = sender.send(change);
+        }
+    }
+
+    /// Write an item, replacing any with the same id
+    pub fn write<T: Serialize>(&self, id: &str, item: &T) {
+        match serde_json::to_vec(item) {
+            Ok(content) => self.send(Change::Write(id.to_owned(), content)),
+            Err(err) => log::error!("failed to serialize {}: {}", id, err),
+        }
+    }
+
+    /// Remove an item
+    pub fn remove(&self, id: &str) {
+        self.send(Change::Remove(id.to_owned()))
+    }
+}
+
+impl Drop for JsonDirWriter {
+    /// Wait for pending changes to be made
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        name: String,
+    }
+
+    fn item(name: &str) -> Item {
+        Item {
+            name: name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_json_dir() {
+        let path = std::env::temp_dir().join(format!("mighty-hooks-store-{}", std::process::id()));
+        let dir = JsonDir::open(&path).unwrap();
+        let writer = JsonDirWriter::spawn(dir.clone(), "test-store").unwrap();
+        writer.write("a", &item("a"));
+        writer.write("b", &item("b"));
+        writer.write("b", &item("replaced"));
+        writer.write("c", &item("c"));
+        writer.remove("c");
+        drop(writer);
+        assert_eq!(item("replaced"), dir.read::<Item>("b").unwrap());
+        assert!(dir.read::<Item>("c").is_err());
+        // corrupt files are skipped, half written files are removed when opened
+        fs::write(path.join("corrupt.json"), "{").unwrap();
+        fs::write(path.join("d.json.tmp"), "{").unwrap();
+        let dir = JsonDir::open(&path).unwrap();
+        assert!(!path.join("d.json.tmp").exists());
+        let mut items = dir.read_all::<Item>().unwrap();
+        items.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(vec![item("a"), item("replaced")], items);
+        assert!(dir.read::<Item>("corrupt").is_err());
+        assert!(dir.remove("a").unwrap());
+        assert!(!dir.remove("a").unwrap());
+        // ids given by a user cannot reach outside the directory
+        fs::write(path.with_extension("json"), "{}").unwrap();
+        let outside = format!("../{}", path.file_name().unwrap().to_string_lossy());
+        let err = dir.remove(&outside).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert!(dir.read::<Item>(&outside).is_err());
+        assert!(path.with_extension("json").exists());
+        fs::remove_file(path.with_extension("json")).unwrap();
+        fs::remove_dir_all(path).unwrap();
+    }
+}
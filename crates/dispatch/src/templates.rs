@@ -0,0 +1,187 @@
+use std::fmt;
+
+use mighty_hooks_config::{Config, HookOut};
+
+use crate::{
+    condition::{self, ConditionErrors},
+    reword::{self, tera_error_message, RewordErrors},
+};
+
+/// A template in the config that could not be compiled
+#[derive(Debug)]
+pub struct TemplateError {
+    /// Key of the hook the template belongs to
+    pub hook: String,
+    /// Where the template is in the hook, e.g. `out[0].reword.content`
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid template in hook \"{}\" at {}: {}",
+            self.hook, self.field, self.message
+        )
+    }
+}
+
+/// Get the name of a template from where it is in the config
+pub(crate) fn template_name(hook_key: &str, out_index: usize, field: &str) -> String {
+    format!("{}#out[{}].{}", hook_key, out_index, field)
+}
+
+/// All templates from the config, compiled once at startup
+#[derive(Debug)]
+pub struct Templates {
+    tera: tera::Tera,
+}
+
+impl Templates {
+    pub fn new() -> Self {
+        let mut tera = tera::Tera::default();
+        tera.autoescape_on(vec![]);
+        Self { tera }
+    }
+
+    /// Compile all templates in the config, returning every one that failed
+    pub fn from_config(config: &Config) -> Result<Self, Vec<TemplateError>> {
+        let mut templates = Self::new();
+        let mut errors = Vec::new();
+        for (hook_key, hook) in &config.hooks {
+            for (index, out) in hook.out.iter().enumerate() {
+                for (field, template) in Self::out_templates(out) {
+                    let name = template_name(hook_key, index, &field);
+                    if let Err(err) = templates.tera.add_raw_template(&name, &template) {
+                        errors.push(TemplateError {
+                            hook: hook_key.clone(),
+                            field: format!("out[{}].{}", index, field),
+                            message: tera_error_message(&err),
+                        });
+                    }
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(templates),
+            false => Err(errors),
+        }
+    }
+
+    /// Get the fields of an out that need compiling, with their template
+    fn out_templates(out: &HookOut) -> Vec<(String, String)> {
+        let mut templates = Vec::new();
+        if reword::is_template(&out.href) {
+            templates.push((String::from("href"), out.href.clone()));
+        }
+        if let Some(when) = &out.when {
+            templates.push((String::from("when"), condition::to_template(when)));
+        }
+        if let Some(reword) = &out.reword {
+            if reword::is_template(&reword.content) {
+                templates.push((String::from("reword.content"), reword.content.clone()));
+            }
+            for (key, value) in &reword.set_headers {
+                if reword::is_template(value) {
+                    templates.push((format!("reword.set_headers.{}", key), value.clone()));
+                }
+            }
+        }
+        templates
+    }
+
+    /// Whether there is a compiled template with this name
+    pub(crate) fn has(&self, name: &str) -> bool {
+        self.tera.templates.contains_key(name)
+    }
+
+    /// Render a compiled template,
+    /// or use the given value if it was not a template
+    pub(crate) fn render(
+        &self,
+        name: &str,
+        value: &str,
+        context: &tera::Context,
+    ) -> Result<String, RewordErrors> {
+        match self.has(name) {
+            true => self
+                .tera
+                .render(name, context)
+                .map_err(|err| RewordErrors::TemplateError(tera_error_message(&err))),
+            false => Ok(value.to_owned()),
+        }
+    }
+
+    /// Evaluate a compiled condition
+    pub(crate) fn evaluate(
+        &self,
+        name: &str,
+        context: &tera::Context,
+    ) -> Result<bool, ConditionErrors> {
+        self.tera
+            .render(name, context)
+            .map(|result| result == "true")
+            .map_err(|err| ConditionErrors::ExpressionError(tera_error_message(&err)))
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(content: &str) -> Config {
+        serde_yaml::from_str(&format!(
+            r#"
+host: 0.0.0.0
+port: 8000
+hooks:
+  hooks.example.com/hello:
+    in:
+      content_type: application/json
+    out:
+      - href: http://internal.example.com/hello
+        reword:
+          content: "{}"
+"#,
+            content
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_config() {
+        let templates = Templates::from_config(&config("{{ content.raw }}")).unwrap();
+        assert!(templates.has(&template_name(
+            "hooks.example.com/hello",
+            0,
+            "reword.content"
+        )));
+    }
+
+    #[test]
+    fn test_render_error() {
+        let templates = Templates::from_config(&config("{{ content.json.missing }}")).unwrap();
+        let name = template_name("hooks.example.com/hello", 0, "reword.content");
+        let err = templates
+            .render(&name, "", &tera::Context::new())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("content.json.missing"), "{}", err);
+    }
+
+    #[test]
+    fn test_from_config_invalid() {
+        let errors = Templates::from_config(&config("line one\\n{{ content.raw ")).unwrap_err();
+        assert_eq!(1, errors.len());
+        assert_eq!("hooks.example.com/hello", errors[0].hook);
+        assert_eq!("out[0].reword.content", errors[0].field);
+        assert!(errors[0].message.contains("2:"), "{}", errors[0].message);
+    }
+}
@@ -1,11 +1,28 @@
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use actix_web::{middleware, HttpResponse};
 use actix_web::{middleware::Logger, post, web, App, HttpRequest, HttpServer};
-use mighty_hooks_config::Config;
-use mighty_hooks_core::Body;
-use mighty_hooks_core::{signing::verify_hmac_sha256, tls::load_rustls_config};
-use mighty_hooks_dispatch::Dispatcher;
+use mighty_hooks_config::{Config, HookAck};
+use mighty_hooks_core::{media::MediaType, metrics::Metrics, Body};
+use mighty_hooks_core::{tls::load_rustls_config, verify::VerifyError};
+use mighty_hooks_dispatch::{
+    Acknowledgement, DeadLetterStore, Decoders, DeliveryQueue, Dispatcher, Incoming, ReplayCache,
+    Signers, Templates,
+};
+use proxy::TrustedProxies;
+use ratelimit::RateLimiter;
+use router::HookRouter;
+use sources::SourceAllowlists;
+use verify::build_verifier;
+
+mod proxy;
+mod ratelimit;
+pub mod router;
+pub mod sources;
+mod verify;
 
 /// Get the value of a header from the request, validating it is not empty
 fn get_header_value(request: &HttpRequest, key: &str) -> Option<String> {
@@ -24,24 +41,24 @@ fn get_in_path(path: String, request: &HttpRequest) -> Option<String> {
     Some(format!("{}/{}", host, path))
 }
 
-/// Get the real client ip from the request
-fn get_client_ip(behind_proxy: bool, request: &HttpRequest) -> Option<String> {
-    match behind_proxy {
-        true => request
-            .connection_info()
-            .realip_remote_addr()
-            .map(|ip| ip.to_owned()),
-        false => request
-            .connection_info()
-            .peer_addr()
-            .map(|ip| ip.to_owned()),
-    }
+/// Get the content type to forward if it is matched by any of the accepted media types,
+/// only keeping the parameters the matching media type has
+fn accepted_content_type(accepted: &[String], content_type: &str) -> Option<String> {
+    let media_type = MediaType::parse(content_type)?;
+    accepted
+        .iter()
+        .filter_map(|pattern| MediaType::parse(pattern))
+        .find(|pattern| pattern.matches(&media_type))
+        .map(|pattern| media_type.matched_as(&pattern))
 }
 
-/// Get the signature-256 from the request headers
-fn get_signature_256(request: &HttpRequest) -> Option<String> {
-    let value = get_header_value(request, "X-Hub-Signature-256")?;
-    value.strip_prefix("sha256=").map(|s| s.to_string())
+/// Respond to a request that is over a rate limit
+fn too_many_requests(wait: Duration) -> HttpResponse {
+    // round up, so the request is allowed when retried
+    let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", seconds.to_string()))
+        .finish()
 }
 
 /// Extract all headers from the request into a HashMap
@@ -54,12 +71,18 @@ fn extract_headers(request: &HttpRequest) -> HashMap<String, String> {
 }
 
 #[post("/{path:.*}")]
+#[allow(clippy::too_many_arguments)]
 async fn post_webhook(
     config: web::Data<Config>,
+    router: web::Data<HookRouter>,
     dispatcher: web::Data<Dispatcher>,
+    replay_cache: web::Data<ReplayCache>,
+    allowlists: web::Data<SourceAllowlists>,
+    proxies: web::Data<TrustedProxies>,
+    limiter: web::Data<RateLimiter>,
     path: web::Path<String>,
     request: HttpRequest,
-    body: web::Bytes,
+    payload: web::Payload,
 ) -> HttpResponse {
     // Get the path from the request data ensuring it is valid
     let in_path = match get_in_path(path.into_inner(), &request) {
@@ -69,33 +92,63 @@ async fn post_webhook(
         }
     };
     // Get the real client ip
-    let client_ip = match get_client_ip(config.behind_proxy, &request) {
+    let client_ip = match proxies.client_ip(&request) {
         Some(client_id) => client_id,
         None => {
             log::error!("failed to get client ip");
             return HttpResponse::InternalServerError().finish();
         }
     };
+    // Limit requests to any hook
+    if let Err(wait) = limiter.check_global(&config.rate_limit, client_ip) {
+        log::info!("{} is rate limited for {:?}", client_ip, wait);
+        return too_many_requests(wait);
+    }
     // Try and find hook for path
-    let hook = match config.hooks.get(&in_path) {
-        Some(hook) => hook,
+    let found = router
+        .find(&in_path)
+        .and_then(|route| config.hooks.get(route.key).map(|hook| (route, hook)));
+    let (route, hook) = match found {
+        Some(found) => found,
         None => {
             // No hook found for path
             log::info!("{} trigged nonexistent hook \"{}\"", client_ip, in_path);
             return HttpResponse::NotFound().finish();
         }
     };
+    // Only accept requests from allowed addresses
+    if !allowlists.is_allowed(route.key, Some(client_ip)) {
+        log::info!(
+            "{} trigged hook \"{}\" from a source that is not allowed",
+            client_ip,
+            in_path
+        );
+        return HttpResponse::Forbidden().finish();
+    }
+    // Limit requests to this hook
+    if let Err(wait) = limiter.check_hook(route.key, &hook.r#in.rate_limit, client_ip) {
+        log::info!(
+            "{} is rate limited for hook \"{}\" for {:?}",
+            client_ip,
+            in_path,
+            wait
+        );
+        return too_many_requests(wait);
+    }
     // Validate content type
-    match get_header_value(&request, "Content-Type") {
+    let content_type = match get_header_value(&request, "Content-Type") {
         Some(content_type) => {
-            if content_type != hook.r#in.content_type {
-                log::info!(
-                    "{} trigged hook \"{}\" with unexpected content type: {}",
-                    client_ip,
-                    in_path,
-                    content_type
-                );
-                return HttpResponse::BadRequest().finish();
+            match accepted_content_type(&hook.r#in.content_type, &content_type) {
+                Some(accepted) => accepted,
+                None => {
+                    log::info!(
+                        "{} trigged hook \"{}\" with unexpected content type: {}",
+                        client_ip,
+                        in_path,
+                        content_type
+                    );
+                    return HttpResponse::BadRequest().finish();
+                }
             }
         }
         None => {
@@ -107,64 +160,267 @@ async fn post_webhook(
             return HttpResponse::BadRequest().finish();
         }
     };
-    // Validate signature-256 if enabled
-    if let Some(secret_256) = &hook.r#in.secret_256 {
-        match get_signature_256(&request) {
-            Some(signature) => {
-                if !verify_hmac_sha256(secret_256, &body, &signature) {
+    // Reject bodies that are too large, by their declared length or while reading
+    let max_body_bytes = hook.r#in.max_body_bytes.unwrap_or(config.max_body_bytes);
+    let declared_length = get_header_value(&request, "Content-Length")
+        .and_then(|length| length.parse::<usize>().ok());
+    if declared_length.is_some_and(|length| length > max_body_bytes) {
+        log::info!(
+            "{} trigged hook \"{}\" with too large body",
+            client_ip,
+            in_path
+        );
+        return HttpResponse::PayloadTooLarge().finish();
+    }
+    // Read the body, now the request is expected
+    let body = match payload.to_bytes_limited(max_body_bytes).await {
+        Ok(Ok(body)) => body,
+        Ok(Err(err)) => {
+            log::info!(
+                "{} trigged hook \"{}\" with unreadable body: {}",
+                client_ip,
+                in_path,
+                err
+            );
+            return HttpResponse::BadRequest().finish();
+        }
+        Err(_) => {
+            log::info!(
+                "{} trigged hook \"{}\" with too large body",
+                client_ip,
+                in_path
+            );
+            return HttpResponse::PayloadTooLarge().finish();
+        }
+    };
+    // Extract all headers from the request
+    let headers = extract_headers(&request);
+    // Validate the signature against each active secret if enabled
+    if hook.r#in.has_secrets() {
+        let verifier = build_verifier(&hook.r#in.verify);
+        let mut result = Err(VerifyError::Invalid);
+        for (name, secret) in hook.r#in.active_secrets(SystemTime::now()) {
+            result = verifier.verify(&headers, &body, secret).map(|_| name);
+            if !matches!(result, Err(VerifyError::Invalid)) {
+                break;
+            }
+        }
+        match result {
+            Ok(name) => log::info!(
+                "{} signed hook \"{}\" with secret \"{}\"",
+                client_ip,
+                in_path,
+                name
+            ),
+            Err(err) => {
+                log::info!("{} trigged hook \"{}\" with {}", client_ip, in_path, err);
+                return HttpResponse::BadRequest().finish();
+            }
+        }
+    }
+    // Skip deliveries that have already been received
+    let replay_key = match &hook.r#in.replay_guard {
+        Some(guard) => match ReplayCache::delivery_key(guard, &headers, &body) {
+            Some(key) => {
+                if !replay_cache.first_seen(route.key, &key, guard) {
                     log::info!(
-                        "{} trigged hook \"{}\" with invalid signature",
+                        "{} trigged hook \"{}\" with already received delivery \"{}\"",
                         client_ip,
-                        in_path
+                        in_path,
+                        key
                     );
-                    return HttpResponse::BadRequest().finish();
+                    return match hook.ack {
+                        HookAck::Immediate => HttpResponse::Accepted().finish(),
+                        _ => HttpResponse::NoContent().finish(),
+                    };
                 }
+                Some(key)
             }
             None => {
                 log::info!(
-                    "{} trigged hook \"{}\" without signature",
+                    "{} trigged hook \"{}\" without delivery id",
                     client_ip,
                     in_path
                 );
                 return HttpResponse::BadRequest().finish();
             }
-        };
-    }
+        },
+        None => None,
+    };
     log::info!("{} trigged hook successfully \"{}\"", client_ip, in_path);
-    // Extract all headers from the request
-    let headers = extract_headers(&request);
     // Send request to all hooks
-    dispatcher
+    let hook_key = route.key.to_owned();
+    let acknowledgement = dispatcher
+        .into_inner()
         .dispatch_hooks(
-            &hook.out,
-            Body {
-                content: body,
-                content_type: hook.r#in.content_type.clone(),
+            Incoming {
+                hook_key: hook_key.clone(),
+                params: route.params,
+                body: Body {
+                    content: body,
+                    content_type,
+                },
+                headers,
             },
-            headers,
+            hook.out.clone(),
+            hook.ack,
         )
         .await;
+    // The sender will send again, which should not be ignored
+    if let (Acknowledgement::Failed, Some(key)) = (acknowledgement, replay_key) {
+        replay_cache.forget(&hook_key, &key);
+    }
+    match (hook.ack, acknowledgement) {
+        (HookAck::Immediate, _) => HttpResponse::Accepted().finish(),
+        (_, Acknowledgement::Received) => HttpResponse::NoContent().finish(),
+        (_, Acknowledgement::Failed) => HttpResponse::BadGateway().finish(),
+        (_, Acknowledgement::Rejected) => HttpResponse::UnprocessableEntity().finish(),
+    }
+}
+
+/// Serve collected metrics in the Prometheus text format
+async fn get_metrics(metrics: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
 
-    HttpResponse::NoContent().finish()
+/// Reload allowed sources files whenever SIGHUP is received
+#[cfg(unix)]
+async fn reload_on_hangup(config: Config, allowlists: web::Data<SourceAllowlists>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            log::error!("failed to listen for SIGHUP: {}", err);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match allowlists.reload(&config) {
+            Ok(()) => log::info!("reloaded allowed sources"),
+            Err(errors) => {
+                for err in errors {
+                    log::error!("{}, keeping previous allowed sources", err);
+                }
+            }
+        }
+    }
 }
 
 pub async fn run_server(config: &Config) {
     let config = config.clone();
     let https_config = config.https.clone();
     let bind = (config.host.to_owned(), config.port);
+    // Check the hook keys before anything is started, refusing to start if any are invalid
+    let router = match HookRouter::new(config.hooks.keys()) {
+        Ok(router) => web::Data::new(router),
+        Err(err) => {
+            log::error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    // Create the dispatcher shared by all workers and start retrying failed deliveries
+    let (queue, dead_letters, replay_cache) = match &config.data_dir {
+        Some(data_dir) => (
+            DeliveryQueue::open(Path::new(data_dir).join("queue"))
+                .expect("Failed to open retry queue"),
+            Some(
+                DeadLetterStore::open(Path::new(data_dir).join("deadletter"))
+                    .expect("Failed to open dead letter store"),
+            ),
+            ReplayCache::open(Path::new(data_dir).join("replay"))
+                .expect("Failed to open replay cache"),
+        ),
+        None => (DeliveryQueue::in_memory(), None, ReplayCache::in_memory()),
+    };
+    let replay_cache = web::Data::new(replay_cache);
+    // Compile all templates, refusing to start if any are invalid
+    let templates = match Templates::from_config(&config) {
+        Ok(templates) => templates,
+        Err(errors) => {
+            for err in errors {
+                log::error!("{}", err);
+            }
+            std::process::exit(1);
+        }
+    };
+    // Load the Protobuf messages bodies are decoded as, refusing to start if any are invalid
+    let decoders = match Decoders::from_config(&config) {
+        Ok(decoders) => decoders,
+        Err(errors) => {
+            for err in errors {
+                log::error!("{}", err);
+            }
+            std::process::exit(1);
+        }
+    };
+    // Load allowed sources, reloading files on SIGHUP
+    let allowlists = match SourceAllowlists::load(&config) {
+        Ok(allowlists) => web::Data::new(allowlists),
+        Err(errors) => {
+            for err in errors {
+                log::error!("{}", err);
+            }
+            std::process::exit(1);
+        }
+    };
+    let proxies = web::Data::new(TrustedProxies::new(&config.trusted_proxies));
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(config.clone(), allowlists.clone()));
+    let metrics = Arc::new(Metrics::new());
+    let limiter = web::Data::new(RateLimiter::new(metrics.clone()));
+    let dispatcher = web::Data::new(Dispatcher::new(
+        queue,
+        dead_letters,
+        templates,
+        decoders,
+        Signers::from_config(&config),
+        metrics.clone(),
+    ));
+    dispatcher.clone().into_inner().spawn_retries();
+    let metrics = web::Data::from(metrics);
+    // Serve metrics on their own address if set, so they can be kept private
+    if let (Some(metrics_bind), Some(metrics_path)) = (&config.metrics_bind, &config.metrics_path) {
+        let (metrics, metrics_path) = (metrics.clone(), metrics_path.clone());
+        let metrics_server = HttpServer::new(move || {
+            App::new()
+                .app_data(metrics.clone())
+                .service(web::resource(&metrics_path).route(web::get().to(get_metrics)))
+        })
+        .workers(1)
+        .bind(metrics_bind)
+        .expect("Failed to bind metrics to address")
+        .run();
+        log::info!("serving metrics on http://{}", metrics_bind);
+        tokio::spawn(metrics_server);
+    }
     // Create server
     let server = HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
             .wrap(middleware::DefaultHeaders::new().add(("Server", "Mighty Hooks")))
             .app_data(web::Data::new(config.clone()))
-            .app_data(web::Data::new(Dispatcher::default()))
+            .app_data(router.clone())
+            .app_data(dispatcher.clone())
+            .app_data(replay_cache.clone())
+            .app_data(allowlists.clone())
+            .app_data(proxies.clone())
+            .app_data(limiter.clone())
+            .app_data(metrics.clone())
+            .configure(|cfg| {
+                if let (Some(metrics_path), None) = (&config.metrics_path, &config.metrics_bind) {
+                    cfg.service(web::resource(metrics_path).route(web::get().to(get_metrics)));
+                }
+            })
             .service(post_webhook)
     });
     // Bind to address & port using either http or https
     let bound_server = match https_config {
         Some(https_config) => {
-            let cert_config = load_rustls_config(&https_config.cert, &https_config.key);
+            let cert_config = load_rustls_config(&https_config.cert, &https_config.key)
+                .expect("Failed to load TLS cert/key");
             server.bind_rustls(bind, cert_config)
         }
         None => server.bind(bind),
@@ -0,0 +1,159 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::HttpRequest;
+use ipnet::IpNet;
+
+use crate::sources::parse_source;
+
+/// Parse an address from a forwarded header,
+/// which may be quoted, include a port, or be an IPv6 address in brackets
+fn parse_forwarded_address(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    value
+        .strip_prefix('[')
+        .and_then(|value| value.split(']').next())
+        .and_then(|value| value.parse::<IpAddr>().ok())
+}
+
+/// Get the `for` addresses from `Forwarded` headers, in order
+fn parse_forwarded(values: &[&str]) -> Vec<String> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                match key.trim().eq_ignore_ascii_case("for") {
+                    true => Some(value.trim().to_owned()),
+                    false => None,
+                }
+            })
+        })
+        .collect()
+}
+
+/// Get the addresses from `X-Forwarded-For` headers, in order
+fn parse_forwarded_for(values: &[&str]) -> Vec<String> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// Proxies trusted to report the address of the client they forward for
+#[derive(Debug, Default)]
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Create from address ranges, invalid ones were already rejected when validating the config
+    pub fn new(values: &[String]) -> Self {
+        Self {
+            nets: values
+                .iter()
+                .filter_map(|value| parse_source(value))
+                .collect(),
+        }
+    }
+
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(ip))
+    }
+
+    /// Find the client's address from the chain of forwarded addresses (oldest first),
+    /// walking right to left from the peer while the address is a trusted proxy,
+    /// IPv4-mapped IPv6 addresses are treated as the IPv4 address they map
+    fn resolve(&self, peer: IpAddr, forwarded: &[String]) -> IpAddr {
+        let mut client = peer.to_canonical();
+        for value in forwarded.iter().rev() {
+            if !self.is_trusted(&client) {
+                break;
+            }
+            match parse_forwarded_address(value) {
+                Some(ip) => client = ip.to_canonical(),
+                // e.g. "unknown" or an obfuscated identifier
+                None => break,
+            }
+        }
+        client
+    }
+
+    /// Get the client's address, only using forwarded headers when the peer is trusted,
+    /// preferring `Forwarded`, then `X-Forwarded-For`, then `X-Real-IP`
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        let peer = request.peer_addr()?.ip().to_canonical();
+        if !self.is_trusted(&peer) {
+            return Some(peer);
+        }
+        let values = |name: &str| -> Vec<&str> {
+            request
+                .headers()
+                .get_all(name)
+                .filter_map(|value| value.to_str().ok())
+                .collect()
+        };
+        let mut forwarded = parse_forwarded(&values("Forwarded"));
+        if forwarded.is_empty() {
+            forwarded = parse_forwarded_for(&values("X-Forwarded-For"));
+        }
+        if forwarded.is_empty() {
+            forwarded = parse_forwarded_for(&values("X-Real-IP"));
+        }
+        Some(self.resolve(peer, &forwarded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_forwarded() {
+        assert_eq!(
+            vec!["192.0.2.43", "\"[2001:db8:cafe::17]:4711\"", "unknown"],
+            parse_forwarded(&[
+                "for=192.0.2.43;proto=https, For=\"[2001:db8:cafe::17]:4711\"",
+                "by=10.0.0.1;for=unknown"
+            ])
+        );
+        assert_eq!(
+            Some(ip("2001:db8:cafe::17")),
+            parse_forwarded_address("\"[2001:db8:cafe::17]:4711\"")
+        );
+        assert_eq!(
+            Some(ip("192.0.2.43")),
+            parse_forwarded_address("192.0.2.43:47011")
+        );
+        assert_eq!(None, parse_forwarded_address("unknown"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let proxies = TrustedProxies::new(&[String::from("10.0.0.0/8")]);
+        let chain = parse_forwarded_for(&["1.1.1.1, 2.2.2.2", "10.0.0.2"]);
+        // the peer is not trusted, so nothing it says is used
+        assert_eq!(ip("3.3.3.3"), proxies.resolve(ip("3.3.3.3"), &chain));
+        // 2.2.2.2 is the first untrusted address, 1.1.1.1 may be spoofed by it
+        assert_eq!(ip("2.2.2.2"), proxies.resolve(ip("10.0.0.1"), &chain));
+        // only trusted proxies in the chain
+        let chain = parse_forwarded_for(&["10.0.0.3"]);
+        assert_eq!(ip("10.0.0.3"), proxies.resolve(ip("10.0.0.1"), &chain));
+        assert_eq!(ip("10.0.0.1"), proxies.resolve(ip("10.0.0.1"), &[]));
+        // a trusted proxy connecting to a dual-stack socket
+        let chain = parse_forwarded_for(&["::ffff:1.1.1.1", "::ffff:10.0.0.2"]);
+        assert_eq!(ip("1.1.1.1"), proxies.resolve(ip("::ffff:10.0.0.1"), &chain));
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use mighty_hooks_config::{RateLimit, RateLimits};
+use mighty_hooks_core::metrics::Metrics;
+
+/// How often full buckets are forgotten
+const PRUNE_EVERY: Duration = Duration::from_secs(60);
+
+/// Longest wait given, as very low rates would otherwise wait for years
+const MAX_WAIT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What a bucket limits requests for
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Scope {
+    Total,
+    Client(IpAddr),
+    Hook(String),
+    HookClient(String, IpAddr),
+}
+
+impl Scope {
+    /// Name used when counting rejections
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Total => "total",
+            Self::Client(_) => "per_client",
+            Self::Hook(_) => "hook_total",
+            Self::HookClient(_, _) => "hook_per_client",
+        }
+    }
+
+    fn hook(&self) -> &str {
+        match self {
+            Self::Hook(hook) | Self::HookClient(hook, _) => hook,
+            _ => "",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    per_second: f64,
+    capacity: f64,
+}
+
+impl Bucket {
+    fn full(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.capacity(),
+            updated: now,
+            per_second: limit.per_second,
+            capacity: limit.capacity(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.capacity
+    }
+
+    /// How long until a request is allowed
+    fn wait(&self) -> Duration {
+        match self.tokens >= 1.0 {
+            true => Duration::ZERO,
+            false => Duration::try_from_secs_f64((1.0 - self.tokens) / self.per_second)
+                .map_or(MAX_WAIT, |wait| wait.min(MAX_WAIT)),
+        }
+    }
+}
+
+/// Address a client is limited by, IPv6 clients by their /64 network
+/// as a single client is usually given a whole /64
+fn client_address(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & !u128::from(u64::MAX)).into()),
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<Scope, Bucket>,
+    /// When full buckets were last forgotten
+    pruned: Instant,
+}
+
+/// Limits requests using token buckets, counting rejected requests
+pub struct RateLimiter {
+    buckets: Mutex<Buckets>,
+    metrics: Arc<Metrics>,
+}
+
+impl RateLimiter {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+            metrics,
+        }
+    }
+
+    /// Take a request from every bucket, only if they all allow it,
+    /// otherwise returning how long to wait before trying again
+    fn take(&self, scopes: &[(Scope, &RateLimit)], now: Instant) -> Result<(), Duration> {
+        let mut state = self.buckets.lock().unwrap();
+        if now.saturating_duration_since(state.pruned) >= PRUNE_EVERY {
+            // full buckets behave the same as new ones
+            state.buckets.retain(|_, bucket| {
+                bucket.refill(now);
+                !bucket.is_full()
+            });
+            state.pruned = now;
+        }
+        let buckets = &mut state.buckets;
+        let mut limited: Option<(&Scope, Duration)> = None;
+        for (scope, limit) in scopes {
+            let bucket = buckets
+                .entry(scope.clone())
+                .or_insert_with(|| Bucket::full(limit, now));
+            bucket.refill(now);
+            let wait = bucket.wait();
+            if wait > limited.map(|(_, wait)| wait).unwrap_or_default() {
+                limited = Some((scope, wait));
+            }
+        }
+        if let Some((scope, wait)) = limited {
+            self.metrics.inc(
+                "mighty_hooks_rate_limited_total",
+                &[("limit", scope.name()), ("hook", scope.hook())],
+            );
+            return Err(wait);
+        }
+        for (scope, _) in scopes {
+            if let Some(bucket) = buckets.get_mut(scope) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the buckets a request is limited by
+    fn scopes<'a>(
+        limits: &'a RateLimits,
+        hook: Option<&str>,
+        client_ip: IpAddr,
+    ) -> Vec<(Scope, &'a RateLimit)> {
+        let mut scopes = Vec::new();
+        if let Some(limit) = &limits.total {
+            let scope = match hook {
+                Some(hook) => Scope::Hook(hook.to_owned()),
+                None => Scope::Total,
+            };
+            scopes.push((scope, limit));
+        }
+        if let Some(limit) = &limits.per_client {
+            let client_ip = client_address(client_ip);
+            let scope = match hook {
+                Some(hook) => Scope::HookClient(hook.to_owned(), client_ip),
+                None => Scope::Client(client_ip),
+            };
+            scopes.push((scope, limit));
+        }
+        scopes
+    }
+
+    /// Check a request against the limits for every hook
+    pub fn check_global(&self, limits: &RateLimits, client_ip: IpAddr) -> Result<(), Duration> {
+        self.take(&Self::scopes(limits, None, client_ip), Instant::now())
+    }
+
+    /// Check a request against a hook's limits
+    pub fn check_hook(
+        &self,
+        hook: &str,
+        limits: &RateLimits,
+        client_ip: IpAddr,
+    ) -> Result<(), Duration> {
+        self.take(&Self::scopes(limits, Some(hook), client_ip), Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        let limiter = RateLimiter::new(Arc::new(Metrics::new()));
+        let limits = RateLimits {
+            total: Some(RateLimit {
+                per_second: 0.5,
+                burst: Some(2),
+            }),
+            per_client: Some(RateLimit {
+                per_second: 1.0,
+                burst: None,
+            }),
+        };
+        let ip = |value: &str| -> IpAddr { value.parse().unwrap() };
+        let now = Instant::now();
+        let take = |ip, now| limiter.take(&RateLimiter::scopes(&limits, Some("hook"), ip), now);
+        assert_eq!(Ok(()), take(ip("10.0.0.1"), now));
+        // the per client limit allows one a second
+        assert_eq!(Err(Duration::from_secs(1)), take(ip("10.0.0.1"), now));
+        assert_eq!(Ok(()), take(ip("10.0.0.2"), now));
+        // the total burst of 2 is used up, refilling one every 2 seconds
+        assert_eq!(Err(Duration::from_secs(2)), take(ip("10.0.0.3"), now));
+        assert_eq!(Ok(()), take(ip("10.0.0.1"), now + Duration::from_secs(2)));
+        assert!(limiter
+            .metrics
+            .render()
+            .contains("mighty_hooks_rate_limited_total{limit=\"hook_total\",hook=\"hook\"} 1"));
+        // full buckets are forgotten
+        let later = now + PRUNE_EVERY;
+        assert_eq!(Ok(()), take(ip("10.0.0.1"), later));
+        assert_eq!(2, limiter.buckets.lock().unwrap().buckets.len());
+    }
+
+    #[test]
+    fn test_client_address() {
+        let ip = |value: &str| -> IpAddr { value.parse().unwrap() };
+        assert_eq!(ip("10.0.0.1"), client_address(ip("10.0.0.1")));
+        // IPv6 clients are limited by their /64
+        assert_eq!(ip("2001:db8:0:1::"), client_address(ip("2001:db8:0:1:2:3:4:5")));
+    }
+
+    #[test]
+    fn test_wait() {
+        let limit = RateLimit {
+            per_second: 1e-300,
+            burst: None,
+        };
+        let mut bucket = Bucket::full(&limit, Instant::now());
+        bucket.tokens = 0.0;
+        assert_eq!(MAX_WAIT, bucket.wait());
+    }
+}
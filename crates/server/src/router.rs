@@ -0,0 +1,288 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use regex::Regex;
+
+/// Hook keys starting with this are regular expressions
+const REGEX_PREFIX: char = '~';
+
+#[derive(Debug)]
+pub struct RouterError {
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for RouterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid hook key \"{}\": {}", self.key, self.message)
+    }
+}
+
+/// Part of a host (label) or path (segment) in a hook key pattern
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Param(String),
+    Wildcard,
+}
+
+impl Part {
+    fn parse(value: &str) -> Self {
+        match value {
+            "*" => Self::Wildcard,
+            _ => match value.strip_prefix('{').and_then(|v| v.strip_suffix('}')) {
+                Some(name) => Self::Param(name.to_owned()),
+                None => Self::Literal(value.to_owned()),
+            },
+        }
+    }
+}
+
+/// A hook key containing wildcards (`*`) or parameters (`{name}`)
+#[derive(Debug)]
+struct Pattern {
+    key: String,
+    host: Vec<Part>,
+    path: Vec<Part>,
+}
+
+impl Pattern {
+    /// Split a hook key into its host and path
+    fn split(key: &str) -> (&str, &str) {
+        key.split_once('/').unwrap_or((key, ""))
+    }
+
+    fn parse(key: &str) -> Self {
+        let (host, path) = Self::split(key);
+        Self {
+            key: key.to_owned(),
+            host: host.split('.').map(Part::parse).collect(),
+            path: path.split('/').map(Part::parse).collect(),
+        }
+    }
+
+    fn is_pattern(&self) -> bool {
+        self.parts().any(|part| !matches!(part, Part::Literal(_)))
+    }
+
+    fn parts(&self) -> impl Iterator<Item = &Part> {
+        self.host.iter().chain(self.path.iter())
+    }
+
+    /// Number of literal parts, used to rank more specific patterns first
+    fn specificity(&self) -> usize {
+        self.parts()
+            .filter(|part| matches!(part, Part::Literal(_)))
+            .count()
+    }
+
+    fn match_parts(
+        parts: &[Part],
+        values: &[&str],
+        case_sensitive: bool,
+        params: &mut HashMap<String, String>,
+    ) -> bool {
+        if parts.len() != values.len() {
+            return false;
+        }
+        for (part, value) in parts.iter().zip(values) {
+            match part {
+                Part::Literal(literal) => {
+                    let matched = match case_sensitive {
+                        true => literal == value,
+                        false => literal.eq_ignore_ascii_case(value),
+                    };
+                    if !matched {
+                        return false;
+                    }
+                }
+                Part::Param(name) => {
+                    if value.is_empty() {
+                        return false;
+                    }
+                    params.insert(name.clone(), value.to_string());
+                }
+                Part::Wildcard => {
+                    if value.is_empty() {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn find(&self, in_path: &str) -> Option<HashMap<String, String>> {
+        let (host, path) = Self::split(in_path);
+        let host: Vec<&str> = host.split('.').collect();
+        let path: Vec<&str> = path.split('/').collect();
+        let mut params = HashMap::new();
+        match Self::match_parts(&self.host, &host, false, &mut params)
+            && Self::match_parts(&self.path, &path, true, &mut params)
+        {
+            true => Some(params),
+            false => None,
+        }
+    }
+}
+
+/// A matched hook
+#[derive(Debug, PartialEq)]
+pub struct RouteMatch<'a> {
+    /// Key of the hook in the config
+    pub key: &'a str,
+    /// Values captured by parameters in the hook key
+    pub params: HashMap<String, String>,
+}
+
+/// Finds the hook for an incoming request,
+/// trying exact keys, then patterns (most specific first), then regular expressions
+#[derive(Debug)]
+pub struct HookRouter {
+    exact: HashSet<String>,
+    patterns: Vec<Pattern>,
+    regexes: Vec<(String, Regex)>,
+}
+
+impl HookRouter {
+    pub fn new<'a>(keys: impl IntoIterator<Item = &'a String>) -> Result<Self, RouterError> {
+        let mut exact = HashSet::new();
+        let mut patterns = Vec::new();
+        let mut regexes = Vec::new();
+        for key in keys {
+            if let Some(expression) = key.strip_prefix(REGEX_PREFIX) {
+                let regex = Regex::new(expression).map_err(|err| RouterError {
+                    key: key.clone(),
+                    message: err.to_string(),
+                })?;
+                regexes.push((key.clone(), regex));
+                continue;
+            }
+            let pattern = Pattern::parse(key);
+            match pattern.is_pattern() {
+                true => patterns.push(pattern),
+                false => {
+                    exact.insert(key.clone());
+                }
+            }
+        }
+        patterns.sort_by(|a, b| {
+            (Reverse(a.specificity()), &a.key).cmp(&(Reverse(b.specificity()), &b.key))
+        });
+        regexes.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(Self {
+            exact,
+            patterns,
+            regexes,
+        })
+    }
+
+    /// Find the hook matching a request's host + path
+    pub fn find(&self, in_path: &str) -> Option<RouteMatch<'_>> {
+        if let Some(key) = self.exact.get(in_path) {
+            return Some(RouteMatch {
+                key,
+                params: HashMap::new(),
+            });
+        }
+        for pattern in &self.patterns {
+            if let Some(params) = pattern.find(in_path) {
+                return Some(RouteMatch {
+                    key: &pattern.key,
+                    params,
+                });
+            }
+        }
+        for (key, regex) in &self.regexes {
+            if let Some(captures) = regex.captures(in_path) {
+                let params = regex
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|name| {
+                        captures
+                            .name(name)
+                            .map(|value| (name.to_owned(), value.as_str().to_owned()))
+                    })
+                    .collect();
+                return Some(RouteMatch { key, params });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(keys: &[&str]) -> HookRouter {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        HookRouter::new(&keys).unwrap()
+    }
+
+    fn params(values: &[(&str, &str)]) -> HashMap<String, String> {
+        values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_exact() {
+        let router = router(&["hooks.example.com/hello"]);
+        let found = router.find("hooks.example.com/hello").unwrap();
+        assert_eq!("hooks.example.com/hello", found.key);
+        assert!(found.params.is_empty());
+        assert_eq!(None, router.find("hooks.example.com/hello/there"));
+    }
+
+    #[test]
+    fn test_host_wildcard() {
+        let router = router(&["*.hooks.example.com/hello"]);
+        assert!(router.find("a.hooks.example.com/hello").is_some());
+        assert!(router.find("A.Hooks.example.com/hello").is_some());
+        assert_eq!(None, router.find("hooks.example.com/hello"));
+        assert_eq!(None, router.find("a.b.hooks.example.com/hello"));
+    }
+
+    #[test]
+    fn test_path_params() {
+        let router = router(&["hooks.example.com/repo/{name}"]);
+        let found = router.find("hooks.example.com/repo/mighty-hooks").unwrap();
+        assert_eq!(params(&[("name", "mighty-hooks")]), found.params);
+        assert_eq!(None, router.find("hooks.example.com/repo/"));
+        assert_eq!(None, router.find("hooks.example.com/repo/a/b"));
+    }
+
+    #[test]
+    fn test_regex() {
+        let router = router(&[r"~^(?P<tenant>[a-z]+)\.example\.com/hooks/(?P<id>\d+)$"]);
+        let found = router.find("acme.example.com/hooks/42").unwrap();
+        assert_eq!(params(&[("tenant", "acme"), ("id", "42")]), found.params);
+        assert_eq!(None, router.find("acme.example.com/hooks/abc"));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let keys = vec![String::from("~(")];
+        assert!(HookRouter::new(&keys).is_err());
+    }
+
+    #[test]
+    fn test_precedence() {
+        let router = router(&[
+            "~.*",
+            "*.example.com/repo/{name}",
+            "a.example.com/repo/{name}",
+            "a.example.com/repo/special",
+        ]);
+        let find_key = |in_path| router.find(in_path).unwrap().key;
+        assert_eq!("a.example.com/repo/special", find_key("a.example.com/repo/special"));
+        assert_eq!("a.example.com/repo/{name}", find_key("a.example.com/repo/other"));
+        assert_eq!("*.example.com/repo/{name}", find_key("b.example.com/repo/other"));
+        assert_eq!("~.*", find_key("b.example.com/other"));
+    }
+}
@@ -0,0 +1,182 @@
+use std::{collections::HashMap, fmt, net::IpAddr, sync::RwLock};
+
+use ipnet::IpNet;
+use mighty_hooks_config::{Config, HookIn};
+
+#[derive(Debug)]
+pub struct SourcesError {
+    pub key: String,
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SourcesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid allowed sources file \"{}\" for hook \"{}\": {}",
+            self.path, self.key, self.message
+        )
+    }
+}
+
+/// Parse an address range, a single address is treated as a range containing only it
+pub fn parse_source(value: &str) -> Option<IpNet> {
+    let value = value.trim();
+    value
+        .parse::<IpNet>()
+        .ok()
+        .or_else(|| value.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Read address ranges from a file,
+/// either one per line (`#` starts a comment) or JSON like GitHub's `/meta` (using `hooks`)
+pub fn read_sources_file(path: &str) -> Result<Vec<IpNet>, String> {
+    let content = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let values: Vec<String> = match serde_json::from_str::<serde_json::Value>(&content) {
+        Ok(serde_json::Value::Array(values)) => values
+            .into_iter()
+            .map(|value| value.as_str().unwrap_or_default().to_owned())
+            .collect(),
+        Ok(serde_json::Value::Object(mut meta)) => match meta.remove("hooks") {
+            Some(serde_json::Value::Array(values)) => values
+                .into_iter()
+                .map(|value| value.as_str().unwrap_or_default().to_owned())
+                .collect(),
+            _ => return Err(String::from("JSON object has no \"hooks\" list")),
+        },
+        _ => content
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_owned())
+            .collect(),
+    };
+    values
+        .iter()
+        .map(|value| parse_source(value).ok_or_else(|| format!("invalid address \"{}\"", value)))
+        .collect()
+}
+
+/// Get every range a hook allows requests from, or None if it allows any
+fn hook_sources(key: &str, hook_in: &HookIn) -> Result<Option<Vec<IpNet>>, SourcesError> {
+    if hook_in.allowed_sources.is_empty() && hook_in.allowed_sources_file.is_none() {
+        return Ok(None);
+    }
+    // invalid values were already rejected when validating the config
+    let mut sources: Vec<IpNet> = hook_in
+        .allowed_sources
+        .iter()
+        .filter_map(|value| parse_source(value))
+        .collect();
+    if let Some(path) = &hook_in.allowed_sources_file {
+        let from_file = read_sources_file(path).map_err(|message| SourcesError {
+            key: key.to_owned(),
+            path: path.clone(),
+            message,
+        })?;
+        sources.extend(from_file);
+    }
+    Ok(Some(sources))
+}
+
+/// Address ranges each hook accepts requests from, reloadable while running
+#[derive(Debug)]
+pub struct SourceAllowlists {
+    hooks: RwLock<HashMap<String, Vec<IpNet>>>,
+}
+
+impl SourceAllowlists {
+    /// Load the allowed sources of every hook, including any from files
+    pub fn load(config: &Config) -> Result<Self, Vec<SourcesError>> {
+        Ok(Self {
+            hooks: RwLock::new(Self::read(config)?),
+        })
+    }
+
+    fn read(config: &Config) -> Result<HashMap<String, Vec<IpNet>>, Vec<SourcesError>> {
+        let mut hooks = HashMap::new();
+        let mut errors = Vec::new();
+        for (key, hook) in &config.hooks {
+            match hook_sources(key, &hook.r#in) {
+                Ok(Some(sources)) => {
+                    hooks.insert(key.clone(), sources);
+                }
+                Ok(None) => {}
+                Err(err) => errors.push(err),
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(hooks),
+            false => Err(errors),
+        }
+    }
+
+    /// Read the files again, keeping the current sources if any fail to load
+    pub fn reload(&self, config: &Config) -> Result<(), Vec<SourcesError>> {
+        let hooks = Self::read(config)?;
+        *self.hooks.write().unwrap() = hooks;
+        Ok(())
+    }
+
+    /// Whether a hook accepts requests from an address,
+    /// IPv4 clients of a dual-stack socket are matched by their IPv4 address
+    pub fn is_allowed(&self, key: &str, ip: Option<IpAddr>) -> bool {
+        match self.hooks.read().unwrap().get(key) {
+            Some(sources) => ip.is_some_and(|ip| {
+                let ip = ip.to_canonical();
+                sources.iter().any(|net| net.contains(&ip))
+            }),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_source() {
+        assert_eq!(
+            Some("192.30.252.0/22".parse().unwrap()),
+            parse_source("192.30.252.0/22")
+        );
+        assert_eq!(
+            Some("10.0.0.1/32".parse().unwrap()),
+            parse_source(" 10.0.0.1 ")
+        );
+        assert_eq!(Some("::1/128".parse().unwrap()), parse_source("::1"));
+        assert_eq!(None, parse_source("10.0.0.300"));
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+host: 0.0.0.0
+port: 8000
+hooks:
+  hooks.example.com/open:
+    in:
+      content_type: application/json
+    out: []
+  hooks.example.com/github:
+    in:
+      content_type: application/json
+      allowed_sources: ["192.30.252.0/22", "2a0a:a440::/29"]
+    out: []
+"#,
+        )
+        .unwrap();
+        let allowlists = SourceAllowlists::load(&config).unwrap();
+        let ip = |value: &str| Some(value.parse().unwrap());
+        assert!(allowlists.is_allowed("hooks.example.com/open", ip("10.0.0.1")));
+        assert!(allowlists.is_allowed("hooks.example.com/github", ip("192.30.253.1")));
+        assert!(allowlists.is_allowed("hooks.example.com/github", ip("2a0a:a440::1")));
+        assert!(allowlists.is_allowed("hooks.example.com/github", ip("::ffff:192.30.253.1")));
+        assert!(!allowlists.is_allowed("hooks.example.com/github", ip("::ffff:10.0.0.1")));
+        assert!(!allowlists.is_allowed("hooks.example.com/github", ip("10.0.0.1")));
+        assert!(!allowlists.is_allowed("hooks.example.com/github", None));
+    }
+}
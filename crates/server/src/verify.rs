@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use mighty_hooks_config::{HookVerify, SignatureEncoding, VerifyScheme};
+use mighty_hooks_core::verify::{
+    Ed25519Verifier, Encoding, HmacVerifier, SlackVerifier, StandardWebhookVerifier,
+    StripeVerifier, TokenVerifier, Verifier, DEFAULT_TOLERANCE,
+};
+
+/// Create the verifier for a hook's scheme, applying any overrides
+pub fn build_verifier(verify: &HookVerify) -> Box<dyn Verifier> {
+    let tolerance = verify
+        .tolerance_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TOLERANCE);
+    let hmac = match verify.scheme {
+        VerifyScheme::Github => HmacVerifier::github(),
+        VerifyScheme::GithubSha1 => HmacVerifier::github_sha1(),
+        VerifyScheme::Gitea => HmacVerifier::gitea(),
+        VerifyScheme::Gogs => HmacVerifier::gogs(),
+        VerifyScheme::Shopify => HmacVerifier::shopify(),
+        VerifyScheme::Stripe => {
+            let stripe = StripeVerifier::new(tolerance);
+            return Box::new(match &verify.header {
+                Some(header) => stripe.with_header(header),
+                None => stripe,
+            });
+        }
+        VerifyScheme::Slack => {
+            let slack = SlackVerifier::new(tolerance);
+            return Box::new(match &verify.header {
+                Some(header) => slack.with_header(header),
+                None => slack,
+            });
+        }
+        VerifyScheme::StandardWebhooks => {
+            let standard = StandardWebhookVerifier::new(tolerance);
+            return Box::new(match &verify.header {
+                Some(header) => standard.with_header(header),
+                None => standard,
+            });
+        }
+        VerifyScheme::Ed25519 => {
+            let ed25519 = Ed25519Verifier::new(tolerance);
+            return Box::new(match &verify.header {
+                Some(header) => ed25519.with_header(header),
+                None => ed25519,
+            });
+        }
+        VerifyScheme::Gitlab => {
+            return Box::new(match &verify.header {
+                Some(header) => TokenVerifier::new(header),
+                None => TokenVerifier::gitlab(),
+            })
+        }
+    };
+    let hmac = match &verify.header {
+        Some(header) => hmac.with_header(header),
+        None => hmac,
+    };
+    let hmac = match verify.encoding {
+        Some(SignatureEncoding::Hex) => hmac.with_encoding(Encoding::Hex),
+        Some(SignatureEncoding::Base64) => hmac.with_encoding(Encoding::Base64),
+        None => hmac,
+    };
+    Box::new(hmac)
+}
@@ -13,6 +13,8 @@ pub enum Command {
     Serve,
     #[clap(about = "Config management")]
     Config(ConfigArgs),
+    #[clap(about = "Manage deliveries that could not be delivered")]
+    Deadletter(DeadletterArgs),
     #[clap(about = "Show Mighty Hooks version")]
     Version,
 }
@@ -25,8 +27,39 @@ pub struct ConfigArgs {
 
 #[derive(Debug, Parser)]
 pub enum ConfigCommand {
-    #[clap(about = "Show loaded config")]
+    #[clap(about = "Show loaded config, with secrets redacted")]
     Show,
     #[clap(about = "Show where config is loaded from")]
-    Find
+    Find,
+    #[clap(about = "Check a config for problems, outputting the result as JSON")]
+    Check {
+        #[clap(help = "Path to the config, defaults to where config is loaded from")]
+        path: Option<String>,
+    },
+}
+
+#[derive(Debug, Parser)]
+pub struct DeadletterArgs {
+    #[clap(subcommand)]
+    pub cmd: DeadletterCommand,
+}
+
+#[derive(Debug, Parser)]
+pub enum DeadletterCommand {
+    #[clap(about = "List failed deliveries")]
+    List,
+    #[clap(about = "Show a failed delivery")]
+    Show { id: String },
+    #[clap(about = "Send failed deliveries again, removing those delivered")]
+    Replay(DeadletterSelect),
+    #[clap(about = "Remove failed deliveries")]
+    Purge(DeadletterSelect),
+}
+
+#[derive(Debug, Parser)]
+pub struct DeadletterSelect {
+    #[clap(required_unless_present = "all", help = "Id of the delivery")]
+    pub id: Option<String>,
+    #[clap(long, conflicts_with = "id", help = "Select all deliveries")]
+    pub all: bool,
 }
@@ -0,0 +1,68 @@
+use mighty_hooks_config::{Config, ConfigError};
+use mighty_hooks_core::tls::load_rustls_config;
+use mighty_hooks_dispatch::{Decoders, Templates};
+use mighty_hooks_server::{router::HookRouter, sources::SourceAllowlists};
+use serde_json::json;
+
+/// Run every check on a config file, returning all problems found
+pub fn check_config(path: &str) -> Vec<ConfigError> {
+    let config = match Config::from_yaml_file(path) {
+        Ok(config) => config,
+        Err(errors) => return errors,
+    };
+    let mut errors = config.validate().err().unwrap_or_default();
+    // only try loading TLS if the files exist, otherwise it was already reported
+    if let Some(https) = config.https.as_ref().filter(|_| errors.is_empty()) {
+        if let Err(err) = load_rustls_config(&https.cert, &https.key) {
+            errors.push(ConfigError::field("https", err.to_string()));
+        }
+    }
+    if let Err(err) = HookRouter::new(config.hooks.keys()) {
+        errors.push(ConfigError::field(
+            format!("hooks[\"{}\"]", err.key),
+            err.message,
+        ));
+    }
+    if let Err(source_errors) = SourceAllowlists::load(&config) {
+        for err in source_errors {
+            let field = format!("hooks[\"{}\"].in.allowed_sources_file", err.key);
+            // files that cannot be opened were already reported
+            if !errors.iter().any(|err| err.field.as_ref() == Some(&field)) {
+                errors.push(ConfigError::field(field, err.message));
+            }
+        }
+    }
+    if let Err(template_errors) = Templates::from_config(&config) {
+        for err in template_errors {
+            errors.push(ConfigError::field(
+                format!("hooks[\"{}\"].{}", err.hook, err.field),
+                err.message,
+            ));
+        }
+    }
+    if let Err(decoder_errors) = Decoders::from_config(&config) {
+        for err in decoder_errors {
+            let field = format!("hooks[\"{}\"].{}", err.hook, err.field);
+            // descriptor files that cannot be opened were already reported
+            let descriptor = format!("{}.descriptor", field);
+            if !errors.iter().any(|err| err.field.as_ref() == Some(&descriptor)) {
+                errors.push(ConfigError::field(field, err.message));
+            }
+        }
+    }
+    errors.into_iter().map(|err| err.with_path(path)).collect()
+}
+
+/// Format the result of a check as JSON
+pub fn to_json(path: &str, errors: &[ConfigError]) -> serde_json::Value {
+    json!({
+        "path": path,
+        "valid": errors.is_empty(),
+        "errors": errors.iter().map(|err| json!({
+            "field": err.field,
+            "line": err.location.map(|(line, _)| line),
+            "column": err.location.map(|(_, column)| column),
+            "message": err.message,
+        })).collect::<Vec<_>>(),
+    })
+}
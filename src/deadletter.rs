@@ -0,0 +1,130 @@
+use std::{
+    path::Path,
+    process::exit,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use mighty_hooks_config::Config;
+use mighty_hooks_core::metrics::Metrics;
+use mighty_hooks_dispatch::{
+    DeadLetter, DeadLetterStore, Decoders, DeliveryQueue, Dispatcher, Signers, Templates,
+};
+
+use crate::args::{DeadletterCommand, DeadletterSelect};
+
+/// Open the dead letter store, exiting if there is not one configured
+fn open_store(config: &Config) -> DeadLetterStore {
+    let data_dir = match &config.data_dir {
+        Some(data_dir) => data_dir,
+        None => {
+            eprintln!("Error: No data_dir is configured, so there are no dead letters");
+            exit(1);
+        }
+    };
+    match DeadLetterStore::open(Path::new(data_dir).join("deadletter")) {
+        Ok(store) => store,
+        Err(err) => {
+            eprintln!("Error: Could not open dead letter store: {}", err);
+            exit(1);
+        }
+    }
+}
+
+/// Get the selected dead letters, exiting if they cannot be read
+fn select(store: &DeadLetterStore, select: &DeadletterSelect) -> Vec<DeadLetter> {
+    let result = match &select.id {
+        Some(id) => store.get(id).map(|dead_letter| vec![dead_letter]),
+        None => store.list(),
+    };
+    match result {
+        Ok(dead_letters) => dead_letters,
+        Err(err) => {
+            eprintln!("Error: Could not read dead letters: {}", err);
+            exit(1);
+        }
+    }
+}
+
+fn format_time(ms: u64) -> String {
+    httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_millis(ms))
+}
+
+pub async fn run(cmd: DeadletterCommand, config: &Config) {
+    let store = open_store(config);
+    match cmd {
+        DeadletterCommand::List => {
+            for dead_letter in select(&store, &DeadletterSelect { id: None, all: true }) {
+                let delivery = dead_letter.delivery;
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    delivery.id,
+                    format_time(dead_letter.failed_at),
+                    delivery.hook,
+                    delivery.href,
+                    delivery.last_error.unwrap_or_default()
+                );
+            }
+        }
+        DeadletterCommand::Show { id } => {
+            let select_id = DeadletterSelect {
+                id: Some(id),
+                all: false,
+            };
+            for dead_letter in select(&store, &select_id) {
+                let delivery = dead_letter.delivery;
+                println!("id: {}", delivery.id);
+                println!("failed at: {}", format_time(dead_letter.failed_at));
+                println!("hook: {}", delivery.hook);
+                println!("href: {}", delivery.href);
+                println!("attempts: {}", delivery.attempts);
+                println!("last error: {}", delivery.last_error.unwrap_or_default());
+                println!("headers:");
+                for (key, value) in &delivery.headers {
+                    println!("  {}: {}", key, value);
+                }
+                println!("body:");
+                println!("{}", String::from_utf8_lossy(&delivery.body));
+            }
+        }
+        DeadletterCommand::Replay(selected) => {
+            // sign again with the configured secrets, as a signature may have expired
+            let dispatcher = Dispatcher::new(
+                DeliveryQueue::in_memory(),
+                None,
+                Templates::default(),
+                Decoders::default(),
+                Signers::from_config(config),
+                Arc::new(Metrics::new()),
+            );
+            let mut failed = false;
+            for dead_letter in select(&store, &selected) {
+                let delivery = dead_letter.delivery;
+                match dispatcher.replay(&delivery).await {
+                    Ok(()) => {
+                        println!("{}\tdelivered", delivery.id);
+                        if let Err(err) = store.remove(&delivery.id) {
+                            eprintln!("Error: Could not remove {}: {}", delivery.id, err);
+                        }
+                    }
+                    Err(err) => {
+                        println!("{}\tfailed: {}", delivery.id, err);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                exit(1);
+            }
+        }
+        DeadletterCommand::Purge(selected) => {
+            for dead_letter in select(&store, &selected) {
+                let id = dead_letter.delivery.id;
+                match store.remove(&id) {
+                    Ok(_) => println!("{}\tpurged", id),
+                    Err(err) => eprintln!("Error: Could not remove {}: {}", id, err),
+                }
+            }
+        }
+    }
+}
@@ -3,15 +3,29 @@ use mighty_hooks_config::{Config, EnvVarConfig};
 use mighty_hooks_server::run_server;
 
 mod args;
+mod check;
+mod deadletter;
 
-/// Load config from file, return config and path to config file
-fn read_config(env_config: &EnvVarConfig) -> (Config, String) {
-    let config_path = env_config
+/// Get the path to the config file
+fn config_path(env_config: &EnvVarConfig) -> String {
+    env_config
         .config_path
         .clone()
-        .unwrap_or_else(|| "config.yaml".to_owned());
-    let config = Config::from_yaml_file(&config_path).expect("Failed to load config file");
-    (config, config_path)
+        .unwrap_or_else(|| "config.yaml".to_owned())
+}
+
+/// Load config from file, return config and path to config file
+fn read_config(env_config: &EnvVarConfig) -> (Config, String) {
+    let config_path = config_path(env_config);
+    match Config::load(&config_path) {
+        Ok(config) => (config, config_path),
+        Err(errors) => {
+            for err in errors {
+                eprintln!("Error: {}", err);
+            }
+            std::process::exit(1);
+        }
+    }
 }
 
 #[tokio::main]
@@ -33,7 +47,7 @@ pub async fn main() {
             // Load config
             let (config, config_path) = read_config(&env_config);
             log::info!("Loading config from '{}'", &config_path);
-            log::debug!("config = {:#?}", &config);
+            log::debug!("config = {:#?}", &config.redacted());
             // log listening address
             match config.https {
                 Some(_) => {
@@ -48,13 +62,25 @@ pub async fn main() {
             args::ConfigCommand::Show => {
                 let (config, config_path) = read_config(&env_config);
                 log::info!("Loading config from '{}'", &config_path);
-                println!("{:#?}", &config);
+                println!("{:#?}", &config.redacted());
             }
             args::ConfigCommand::Find => {
                 let (_, config_path) = read_config(&env_config);
                 println!("{}", &config_path);
             }
+            args::ConfigCommand::Check { path } => {
+                let path = path.unwrap_or_else(|| config_path(&env_config));
+                let errors = check::check_config(&path);
+                println!("{:#}", check::to_json(&path, &errors));
+                if !errors.is_empty() {
+                    std::process::exit(1);
+                }
+            }
         },
+        args::Command::Deadletter(deadletter_args) => {
+            let (config, _) = read_config(&env_config);
+            deadletter::run(deadletter_args.cmd, &config).await;
+        }
         args::Command::Version => println!("Mighty Hooks {}", env!("CARGO_PKG_VERSION")),
     }
 }